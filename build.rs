@@ -0,0 +1,59 @@
+// Captures build metadata for --version and the version banner.
+// Everything falls back to "unknown", so building outside of a git checkout still works.
+use std::process::Command;
+
+fn git_sha() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .filter(|sha| !sha.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// The version of a dependency as resolved in Cargo.lock
+fn locked_version(lock: &str, name: &str) -> String {
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == format!("name = \"{}\"", name) {
+            if let Some(version) = lines.next().and_then(|line| line.split('"').nth(1)) {
+                return version.to_string();
+            }
+        }
+    }
+    "unknown".to_string()
+}
+
+fn main() {
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    println!("cargo:rustc-env=PGTPS_GIT_SHA={}", git_sha());
+    println!(
+        "cargo:rustc-env=PGTPS_BUILD_DATE={}",
+        chrono::Utc::now().format("%Y-%m-%d")
+    );
+    println!(
+        "cargo:rustc-env=PGTPS_POSTGRES_VERSION={}",
+        locked_version(&lock, "postgres")
+    );
+    for path in git_watched() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
+
+// A commit on the current branch changes the ref HEAD points to (or packed-refs after a gc), not HEAD itself.
+// Paths that do not exist are left out, since cargo would rerun the build script on every build for them.
+fn git_watched() -> Vec<String> {
+    let head = std::fs::read_to_string(".git/HEAD").unwrap_or_default();
+    let mut paths = vec![".git/HEAD".to_string(), ".git/packed-refs".to_string()];
+    if let Some(reference) = head.trim().strip_prefix("ref: ") {
+        paths.push(format!(".git/{}", reference));
+    }
+    paths
+        .into_iter()
+        .filter(|path| std::path::Path::new(path).exists())
+        .collect()
+}
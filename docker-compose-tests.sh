@@ -15,5 +15,8 @@ for ((i=0;i<60;i++)); do
   sleep 1
 done
 
+# a role that authenticates with SCRAM over a connection without TLS (see pg_hba.conf)
+docker-compose exec -u postgres postgres psql -c "create role scram login password 'pg_tps_optimizer'" || echo role exists
+export TEST_SCRAM_CONNSTR="host=localhost port=5432 user=scram password=pg_tps_optimizer dbname=postgres sslmode=disable"
 cargo test -- --include-ignored
 docker-compose up --exit-code-from pg_tps_optimizer pg_tps_optimizer
@@ -0,0 +1,189 @@
+/*
+Aggregate collects the results of multiple sweeps (see --repeat) per number of clients.
+For every number of clients we can then report median, mean and standard deviation across runs,
+and the coefficient of variation (stddev as percentage of the mean),
+which tells how much the results of a single run can be trusted.
+*/
+use crate::threader::TestResult;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+// Summary describes a set of measurements of the same thing
+pub struct Summary {
+    pub runs: usize,
+    pub median: f64,
+    pub mean: f64,
+    pub stddev: f64,
+    // coefficient of variation, stddev as a percentage of mean
+    pub cv: f64,
+}
+
+impl Summary {
+    pub fn from_values(values: &[f64]) -> Summary {
+        let runs = values.len();
+        if runs == 0 {
+            return Summary {
+                runs,
+                median: 0.0,
+                mean: 0.0,
+                stddev: 0.0,
+                cv: 0.0,
+            };
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = match runs % 2 {
+            0 => (sorted[runs / 2 - 1] + sorted[runs / 2]) / 2.0,
+            _ => sorted[runs / 2],
+        };
+        let mean = values.iter().sum::<f64>() / runs as f64;
+        let stddev = (values
+            .iter()
+            .map(|value| (value - mean) * (value - mean))
+            .sum::<f64>()
+            / runs as f64)
+            .sqrt();
+        let cv = match mean == 0.0 {
+            true => 0.0,
+            false => 100.0 * stddev / mean,
+        };
+        Summary {
+            runs,
+            median,
+            mean,
+            stddev,
+            cv,
+        }
+    }
+}
+
+// When a run collected its result (see TestResult::window)
+pub type Window = (DateTime<Utc>, DateTime<Utc>);
+
+// Two sided critical values of student's t distribution for 95% confidence, by degrees of freedom (1 to 30)
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+// Half the width of the 95% confidence interval of a mean over samples with this standard deviation
+// (the mean is within plus or minus this value), or None when one sample tells nothing about the spread
+pub fn confidence_95(stddev: f64, samples: usize) -> Option<f64> {
+    if samples < 2 {
+        return None;
+    }
+    // for many samples the t distribution approaches the normal distribution
+    let t = T_95.get(samples - 2).copied().unwrap_or(1.96);
+    Some(t * stddev / (samples as f64).sqrt())
+}
+
+pub struct Aggregate {
+    tps: BTreeMap<u32, Vec<f64>>,
+    latency: BTreeMap<u32, Vec<f64>>,
+    // the result of every run
+    results: BTreeMap<u32, Vec<TestResult>>,
+}
+
+impl Aggregate {
+    pub fn new() -> Aggregate {
+        Aggregate {
+            tps: BTreeMap::new(),
+            latency: BTreeMap::new(),
+            results: BTreeMap::new(),
+        }
+    }
+    // add the result of one run for a number of clients
+    pub fn add(&mut self, clients: u32, result: &TestResult) {
+        self.tps.entry(clients).or_default().push(result.tps);
+        self.latency
+            .entry(clients)
+            .or_default()
+            .push(result.latency.num_microseconds().unwrap_or(0) as f64);
+        self.results.entry(clients).or_default().push(*result);
+    }
+    // per run the result for a number of clients
+    pub fn results(&self, clients: u32) -> Vec<TestResult> {
+        self.results.get(&clients).cloned().unwrap_or_default()
+    }
+    // per run the measurement window for a number of clients
+    pub fn windows(&self, clients: u32) -> Vec<Window> {
+        self.results(clients)
+            .iter()
+            .filter_map(|result| result.window)
+            .collect()
+    }
+    // per number of clients a summary of tps and latency (in usec)
+    pub fn summaries(&self) -> Vec<(u32, Summary, Summary)> {
+        self.tps
+            .iter()
+            .map(|(clients, tps)| {
+                let latency = self.latency.get(clients).cloned().unwrap_or_default();
+                (
+                    *clients,
+                    Summary::from_values(tps),
+                    Summary::from_values(&latency),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary::from_values(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(summary.runs, 8);
+        assert_eq!(summary.median, 4.5);
+        assert_eq!(summary.mean, 5.0);
+        assert_eq!(summary.stddev, 2.0);
+        assert_eq!(summary.cv, 40.0);
+        let summary = Summary::from_values(&[3.0, 1.0, 2.0]);
+        assert_eq!(summary.median, 2.0);
+        let summary = Summary::from_values(&[]);
+        assert_eq!(summary.runs, 0);
+        assert_eq!(summary.cv, 0.0);
+    }
+
+    #[test]
+    fn test_confidence_95() {
+        assert!(confidence_95(10.0, 1).is_none());
+        // t is 2.262 for 9 degrees of freedom
+        assert_eq!(confidence_95(3.0, 10).unwrap(), 2.262 * 3.0 / 10_f64.sqrt());
+        assert!((confidence_95(10.0, 100).unwrap() - 1.96).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut aggregate = Aggregate::new();
+        for (clients, tps) in [(1, 100.0), (2, 150.0), (1, 300.0)] {
+            aggregate.add(
+                clients,
+                &TestResult {
+                    stable: true,
+                    tps,
+                    latency: Duration::microseconds(tps as i64),
+                    overhead: Duration::zero(),
+                    rollback_ratio: 0.0,
+                    samples: 10,
+                    stddev: None,
+                    window: None,
+                    trace: None,
+                    confidence: None,
+                },
+            );
+        }
+        let summaries = aggregate.summaries();
+        assert_eq!(summaries.len(), 2);
+        let (clients, tps, latency) = &summaries[0];
+        assert_eq!(*clients, 1);
+        assert_eq!(tps.runs, 2);
+        assert_eq!(tps.mean, 200.0);
+        assert_eq!(latency.median, 200.0);
+        assert_eq!(summaries[1].1.runs, 1);
+    }
+}
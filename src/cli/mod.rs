@@ -1,27 +1,68 @@
+use crate::cost::Objective;
 use crate::dsn::Dsn;
+use crate::error::PgTpsError;
+use crate::fibonacci::Fibonacci;
 use crate::generic;
-use crate::threader::workload::Workload;
+use crate::report::OutputFormat;
+use crate::threader::workload::{Isolation, ParamType, Workload};
 use duration_string::DurationString;
+use std::collections::BTreeMap;
 use structopt::StructOpt;
 
 /// Search for a pattern in a file and display the lines that contain it.
 
 #[derive(StructOpt)]
-#[structopt(about = "I detect maximum TPS with minimal latency. Pass `-h` for more info.")]
+#[structopt(
+    about = "I detect maximum TPS with minimal latency. Pass `-h` for more info.",
+    version = crate::version::VERSION
+)]
 pub struct Params {
-    /// Connection string
+    /// Connection strings
     #[structopt(
-        default_value,
         short,
+        long = "dsn",
+        number_of_values = 1,
+        help = "the DSN to connect to (or use env vars PG...), set multiple times to run against every one of them"
+    )]
+    pub dsns: Vec<String>,
+
+    /// dsn_env
+    #[structopt(
+        default_value = "",
         long,
-        help = "the DSN to connect to (or use env vars PG...)"
+        help = "the environment variable to read the DSN from (e.g. DATABASE_URL) when dsn is not set, instead of PGTPSSOURCE."
     )]
+    pub dsn_env: String,
+
+    /// service
+    #[structopt(
+        default_value = "",
+        long,
+        help = "a service from pg_service.conf (PGSERVICEFILE, ~/.pg_service.conf or /etc/pg_service.conf) to connect to, which dsn can override."
+    )]
+    pub service: String,
+
+    /// The DSN of the target that we are running against (one of dsns)
+    #[structopt(skip)]
     pub dsn: String,
 
     /// Query
-    #[structopt(default_value, short, long, help = "the query to run")]
+    #[structopt(
+        default_value,
+        short,
+        long,
+        help = "the query every client runs instead of updating the benchmark table."
+    )]
     pub query: String,
 
+    /// Query file
+    #[structopt(
+        default_value,
+        long,
+        help = "a file with the query every client runs instead of updating the benchmark table."
+    )]
+    pub query_file: String,
+
     /// Prepared queries
     #[structopt(
         short,
@@ -44,6 +85,14 @@ pub struct Params {
     )]
     pub range: String,
 
+    /// steps_file
+    #[structopt(
+        default_value,
+        long,
+        help = "a file with the numbers of clients to run (one per line), instead of the fibonacci steps within range."
+    )]
+    pub steps_file: String,
+
     /// spread
     #[structopt(
         default_value,
@@ -62,6 +111,15 @@ pub struct Params {
     )]
     pub min_samples: u32,
 
+    /// window
+    #[structopt(
+        default_value,
+        short = "w",
+        long,
+        help = "number of latest samples that should be within spread (defaults to min_samples + 1)."
+    )]
+    pub window: u32,
+
     /// max_wait
     #[structopt(
         default_value = "",
@@ -70,20 +128,612 @@ pub struct Params {
         help = "Give it this ammount of seconds before we decide it wil never stabilize."
     )]
     pub max_wait: String,
+
+    /// stall_timeout
+    #[structopt(
+        default_value = "",
+        long,
+        help = "abort when no samples at all arrive for this long (0s disables the watchdog)."
+    )]
+    pub stall_timeout: String,
+
+    /// heartbeat
+    #[structopt(
+        default_value = "",
+        long,
+        help = "while waiting for a step to stabilize, log the interim results to stderr this often (0s disables it)."
+    )]
+    pub heartbeat: String,
+
+    /// max_idle_in_transaction
+    #[structopt(
+        default_value = "",
+        long,
+        help = "warn when a client holds a transaction open without running a statement for longer than this, at the end of a step (0s disables it)."
+    )]
+    pub max_idle_in_transaction: String,
+
+    /// trim_outliers
+    #[structopt(
+        default_value,
+        long,
+        help = "leave this percentage of the highest and of the lowest timeslices out of mean and stddev (0 keeps all of them)."
+    )]
+    pub trim_outliers: f64,
+
+    /// progress
+    #[structopt(
+        long,
+        help = "after every step, log a progress bar with the estimated time for the remaining steps to stderr."
+    )]
+    pub progress: bool,
+
+    /// progress_weighted
+    #[structopt(
+        long,
+        help = "with --progress, estimate that steps take time in proportion to their number of clients."
+    )]
+    pub progress_weighted: bool,
+
+    /// events
+    #[structopt(
+        long,
+        help = "log the lifecycle of the run (start, every step and its result, warnings and the end) to stderr as newline delimited json."
+    )]
+    pub events: bool,
+
+    /// rollback_ratio
+    #[structopt(
+        default_value,
+        long,
+        help = "percentage of transactions (with --transactional) that roll back instead of commit."
+    )]
+    pub rollback_ratio: f64,
+
+    /// prepared_variants
+    #[structopt(
+        default_value,
+        long,
+        help = "with --prepared, let the transactions of every client take turns on this many variants of the prepared statement (default 1)."
+    )]
+    pub prepared_variants: u32,
+
+    /// batch_commit
+    #[structopt(
+        default_value,
+        long,
+        help = "run this many statements in every transaction (with --transactional) before committing."
+    )]
+    pub batch_commit: u32,
+
+    /// count_statements
+    #[structopt(
+        long,
+        help = "with --batch-commit, count every statement instead of every transaction in TPS and Latency."
+    )]
+    pub count_statements: bool,
+
+    /// count_rows
+    #[structopt(
+        long,
+        help = "report the rows affected per second and per transaction after the table, to verify a write workload actually writes."
+    )]
+    pub count_rows: bool,
+
+    /// shuffle
+    #[structopt(
+        long,
+        help = "with --batch-commit, update the rows of other clients too, in a random order per transaction (which can deadlock)."
+    )]
+    pub shuffle: bool,
+
+    /// batch_stats
+    #[structopt(
+        long,
+        help = "report the requested and achieved batch size and sample duration per timeslice after the table."
+    )]
+    pub batch_stats: bool,
+
+    /// cold_latency
+    #[structopt(
+        long,
+        help = "report the latency of the first transaction of every session (cold) and of all others (warm) after the table."
+    )]
+    pub cold_latency: bool,
+
+    /// max_ramp_rate
+    #[structopt(
+        default_value,
+        long,
+        help = "the maximum number of clients to add at once, the rest of a step is added in sub steps before measuring (0 means no maximum)."
+    )]
+    pub max_ramp_rate: u32,
+
+    /// initial_batch
+    #[structopt(
+        default_value,
+        long,
+        help = "number of queries in the first sample of every client, before adapting to its TPS (default 100)."
+    )]
+    pub initial_batch: u32,
+
+    /// fixed_batch
+    #[structopt(
+        default_value,
+        long,
+        help = "run exactly this many queries in every sample, instead of adapting to TPS (0 means adaptive)."
+    )]
+    pub fixed_batch: u32,
+
+    /// connect_timeout
+    #[structopt(
+        default_value = "",
+        long,
+        help = "how long new clients of a step may take to connect, before they are considered refused."
+    )]
+    pub connect_timeout: String,
+
+    /// keepalives_idle
+    #[structopt(
+        default_value,
+        long,
+        help = "seconds a connection may be idle before tcp keepalives are sent (0 leaves it to the dsn or the os)."
+    )]
+    pub keepalives_idle: u32,
+
+    /// keepalives_interval
+    #[structopt(
+        default_value,
+        long,
+        help = "seconds in between unanswered tcp keepalives (0 leaves it to the dsn or the os)."
+    )]
+    pub keepalives_interval: u32,
+
+    /// max_refused
+    #[structopt(
+        long,
+        help = "stop the sweep when more than this percentage of new clients of a step is refused (100 never stops, default 50)."
+    )]
+    pub max_refused: Option<f64>,
+
+    /// find_limit
+    #[structopt(
+        long,
+        help = "keep adding clients until connections are refused (see --max-refused) or queries fail (see --max-errors)."
+    )]
+    pub find_limit: bool,
+
+    /// max_errors
+    #[structopt(
+        long,
+        help = "with --find-limit, stop when more than this percentage of the transactions of a step fails (default 1)."
+    )]
+    pub max_errors: Option<f64>,
+
+    /// transactions_per_session
+    #[structopt(
+        default_value,
+        long,
+        help = "reconnect after this many transactions per worker (0 means never reconnect)."
+    )]
+    pub transactions_per_session: u32,
+
+    /// compare
+    #[structopt(
+        long,
+        number_of_values = 2,
+        value_names = &["BEFORE", "AFTER"],
+        help = "compare the --json documents of two earlier runs and exit (without connecting), failing on a regression."
+    )]
+    pub compare: Vec<String>,
+
+    /// regression_threshold
+    #[structopt(
+        long,
+        help = "with --compare, the percentage TPS may drop or latency may rise before it is a regression (default 5)."
+    )]
+    pub regression_threshold: Option<f64>,
+
+    /// print_dsn
+    #[structopt(
+        long,
+        help = "print the effective DSN (password redacted) and exit without connecting."
+    )]
+    pub print_dsn: bool,
+
+    /// probe
+    #[structopt(
+        long,
+        help = "print what the server supports (version, extensions, standby, max_connections and the usable features) as json and exit."
+    )]
+    pub probe: bool,
+
+    /// monitor
+    #[structopt(
+        long,
+        help = "run no clients, but print the TPS, wal rate and cache hit ratio of the server every monitor_interval until interrupted."
+    )]
+    pub monitor: bool,
+
+    /// monitor_interval
+    #[structopt(
+        default_value = "",
+        long,
+        help = "with --monitor, how often to sample the server."
+    )]
+    pub monitor_interval: String,
+
+    /// pre_connect
+    #[structopt(
+        long,
+        help = "connect and initialize all clients before measuring a step."
+    )]
+    pub pre_connect: bool,
+
+    /// notify
+    #[structopt(
+        long,
+        help = "benchmark LISTEN/NOTIFY: half of the clients notify, the other half listen."
+    )]
+    pub notify: bool,
+
+    /// wal_stress
+    #[structopt(
+        long,
+        help = "insert wide rows instead of updating, to measure how much wal (and replication) the server sustains."
+    )]
+    pub wal_stress: bool,
+
+    /// update_expr
+    #[structopt(
+        default_value = "",
+        long,
+        help = "the SET clause of the built in update (e.g. 'counter = counter + 1'), instead of the no-op id=$1."
+    )]
+    pub update_expr: String,
+
+    /// call
+    #[structopt(
+        default_value = "",
+        long,
+        help = "a stored procedure (e.g. 'my_proc($1)') every client CALLs instead of updating the benchmark table, where $1 is the id of the client."
+    )]
+    pub call: String,
+
+    /// tablespace
+    #[structopt(
+        default_value = "",
+        long,
+        help = "create the benchmark tables and indexes in this tablespace, e.g. to compare storage."
+    )]
+    pub tablespace: String,
+
+    /// reuse_table
+    #[structopt(
+        long,
+        help = "keep the rows of earlier runs in the benchmark tables instead of truncating them (benchmark a warm table)."
+    )]
+    pub reuse_table: bool,
+
+    /// insert
+    #[structopt(
+        long,
+        help = "insert a new row per transaction into an ever growing table instead of updating (an append only log)."
+    )]
+    pub insert: bool,
+
+    /// wait_events
+    #[structopt(
+        long,
+        help = "report what active backends are waiting on (from pg_stat_activity) for every step."
+    )]
+    pub wait_events: bool,
+
+    /// pg_stat_io
+    #[structopt(
+        long,
+        help = "report the reads, writes and extends per second by io context (from pg_stat_io, PostgreSQL 16+) for every step."
+    )]
+    pub pg_stat_io: bool,
+
+    /// pg_stat_statements
+    #[structopt(
+        long,
+        help = "reset pg_stat_statements at start and print the top statements at the end."
+    )]
+    pub pg_stat_statements: bool,
+
+    /// min_tps
+    #[structopt(
+        default_value,
+        long,
+        help = "stop the sweep once a step delivers less TPS than this (0 means no floor)."
+    )]
+    pub min_tps: f64,
+
+    /// max_background_tps
+    #[structopt(
+        default_value,
+        long,
+        help = "sample the TPS of the server for 2s at startup, and abort when other activity exceeds this (0 means no check)."
+    )]
+    pub max_background_tps: f64,
+
+    /// repeat
+    #[structopt(
+        default_value,
+        long,
+        help = "run the entire sweep this many times and summarize the results across runs."
+    )]
+    pub repeat: u32,
+
+    /// pin_cpus
+    #[structopt(long, help = "pin every client thread to a cpu (round robin).")]
+    pub pin_cpus: bool,
+
+    /// pgbouncer
+    #[structopt(
+        long,
+        help = "be compatible with pgbouncer in transaction pooling mode (no prepared statements)."
+    )]
+    pub pgbouncer: bool,
+
+    /// temp_table
+    #[structopt(
+        long,
+        help = "have every client use a temp table of its own instead of the shared benchmark table."
+    )]
+    pub temp_table: bool,
+
+    /// without_index
+    #[structopt(
+        long,
+        help = "do not index the id column of the benchmark table, so every statement does a seq scan."
+    )]
+    pub without_index: bool,
+
+    /// direct_dsn
+    #[structopt(
+        default_value,
+        long,
+        help = "the DSN to create the table and sample statistics, bypassing the pooler (defaults to --dsn)"
+    )]
+    pub direct_dsn: String,
+
+    /// setup_file
+    #[structopt(
+        default_value,
+        long,
+        help = "a sql script to run once before the sweep (e.g. to create an extension or fill a table)."
+    )]
+    pub setup_file: String,
+
+    /// teardown_file
+    #[structopt(
+        default_value,
+        long,
+        help = "a sql script to run once after the sweep."
+    )]
+    pub teardown_file: String,
+
+    /// think_time
+    #[structopt(
+        default_value = "",
+        long,
+        help = "let every client pause this long (e.g. 10ms) after every transaction (default 0s)."
+    )]
+    pub think_time: String,
+
+    /// stagger_start
+    #[structopt(
+        long,
+        help = "let every client wait a random 0-200ms before its first transaction, so that the samples of the clients do not start and end together."
+    )]
+    pub stagger_start: bool,
+
+    /// target_latency
+    #[structopt(
+        default_value = "",
+        long,
+        help = "search the highest number of clients with a latency of at most this (e.g. 5ms), instead of a sweep."
+    )]
+    pub target_latency: String,
+
+    /// target_cpu_utilization
+    #[structopt(
+        default_value,
+        long,
+        help = "search the highest number of clients with a server CPU utilization (percent, see --cpu-probe) of at most this, instead of a sweep."
+    )]
+    pub target_cpu_utilization: f64,
+
+    /// cpu_probe
+    #[structopt(
+        default_value = "",
+        long,
+        help = "a command (run with sh -c) that prints the CPU utilization of the server as a number from 0 to 100."
+    )]
+    pub cpu_probe: String,
+
+    /// transactions_per_step
+    #[structopt(
+        default_value,
+        long,
+        help = "run every step until all clients together ran this many transactions, instead of until it is stable, and report how long it took."
+    )]
+    pub transactions_per_step: u32,
+
+    /// burst_on
+    #[structopt(
+        default_value = "",
+        long,
+        help = "run in bursts: measure every step for this long (e.g. 10s)."
+    )]
+    pub burst_on: String,
+
+    /// burst_off
+    #[structopt(
+        default_value = "",
+        long,
+        help = "run in bursts: let all clients idle this long after every burst."
+    )]
+    pub burst_off: String,
+
+    /// param_type
+    #[structopt(
+        default_value = "",
+        long,
+        help = "the type of the benchmark table column and the $1 parameter (oid, int4, int8 or text)."
+    )]
+    pub param_type: String,
+
+    /// isolation
+    #[structopt(
+        default_value = "",
+        long,
+        help = "the isolation level of every transaction (read-committed, repeatable-read or serializable), requires --transactional."
+    )]
+    pub isolation: String,
+
+    /// label
+    #[structopt(
+        default_value,
+        long,
+        help = "a label to describe this run in the output."
+    )]
+    pub label: String,
+
+    /// tags
+    #[structopt(
+        long = "tag",
+        number_of_values = 1,
+        help = "a key=value pair to describe this run in the output (can be set multiple times)."
+    )]
+    pub tags: Vec<String>,
+
+    /// textfile
+    #[structopt(
+        default_value,
+        long,
+        help = "write the final results to this file for the textfile collector of node_exporter."
+    )]
+    pub textfile: String,
+
+    /// openmetrics
+    #[structopt(
+        long,
+        help = "write the textfile in the OpenMetrics text format instead of the Prometheus text format."
+    )]
+    pub openmetrics: bool,
+
+    /// exemplars
+    #[structopt(
+        long,
+        help = "tag a transaction per sample with a trace id (as application_name) and expose it as exemplar (needs --openmetrics)."
+    )]
+    pub exemplars: bool,
+
+    /// output_format
+    #[structopt(
+        default_value = "",
+        long,
+        help = "the format of the results table: table (ascii) or markdown (GitHub flavored)."
+    )]
+    pub output_format: String,
+
+    /// optimize_for
+    #[structopt(
+        default_value = "",
+        long,
+        help = "what the optimum after the results table optimizes for: tps, latency or tps-per-latency (the default)."
+    )]
+    pub optimize_for: String,
+
+    /// cost_expr
+    #[structopt(
+        default_value = "",
+        long,
+        help = "an expression over clients, tps, latency_usec, latency_ms and overhead_usec (e.g. 'tps - 2*latency_ms') that the optimum maximizes, instead of --optimize-for."
+    )]
+    pub cost_expr: String,
+
+    /// json
+    #[structopt(
+        default_value,
+        long,
+        help = "write the final results to this file as a versioned json document."
+    )]
+    pub json: String,
+
+    /// msgpack
+    #[structopt(
+        default_value,
+        long,
+        help = "stream the result of every step to this file as length prefixed MessagePack records."
+    )]
+    pub msgpack: String,
+
+    /// measure_rtt
+    #[structopt(
+        long,
+        help = "measure the network round trip time at startup and also report latency without it."
+    )]
+    pub measure_rtt: bool,
+}
+
+fn as_duration(name: &str, value: &str) -> Result<chrono::Duration, PgTpsError> {
+    DurationString::from_string(value.to_string())
+        .ok()
+        .and_then(|ds| chrono::Duration::from_std(ds.into()).ok())
+        .ok_or_else(|| {
+            PgTpsError::Config(format!(
+                "invalid value for {}: {} is not a Duration",
+                name, value
+            ))
+        })
+}
+
+// The procedure without the (optional) call keyword it may start with, nor a trailing semicolon
+fn as_call(call: &str) -> String {
+    let call = call.trim().trim_end_matches(';').trim_end();
+    match call.get(..5) {
+        Some(keyword) if keyword.eq_ignore_ascii_case("call ") => call[5..].trim().to_string(),
+        _ => call.to_string(),
+    }
+}
+
+// The SET clause without the (optional) set keyword it may start with
+fn as_update_expr(update_expr: &str) -> String {
+    let update_expr = update_expr.trim();
+    match update_expr.get(..4) {
+        Some(keyword) if keyword.eq_ignore_ascii_case("set ") => {
+            update_expr[4..].trim().to_string()
+        }
+        _ => update_expr.to_string(),
+    }
 }
 
 impl Params {
     fn from_args() -> Params {
         <Params as StructOpt>::from_args()
     }
-    pub fn get_args() -> Params {
+    pub fn get_args() -> Result<Params, PgTpsError> {
         let mut args = Params::from_args();
-        args.dsn = generic::get_env_str(&args.dsn, &String::from("PGTPSSOURCE"), "");
-        args.query = generic::get_env_str(
-            &args.query,
-            &String::from("PGTPSQUERY"),
-            &String::from("select * from pg_tables"),
-        );
+        if args.dsns.is_empty() {
+            // e.g. a secret that is exposed as DATABASE_URL, falling back to PGTPSSOURCE when it is not set
+            let from_dsn_env = match args.dsn_env.is_empty() {
+                true => "".to_string(),
+                false => generic::get_env_str("", &args.dsn_env, ""),
+            };
+            args.dsns = vec![generic::get_env_str(&from_dsn_env, "PGTPSSOURCE", "")];
+        }
+        args.dsn = args.dsns[0].to_string();
+        args.service = generic::get_env_str(&args.service, "PGSERVICE", "");
+        args.query_file = generic::get_env_str(&args.query_file, "PGTPSQUERYFILE", "");
+        // with a query file, query is only set when it was set explicitly (which as_query refuses)
+        if args.query_file.is_empty() {
+            args.query = generic::get_env_str(&args.query, &String::from("PGTPSQUERY"), "");
+        }
         args.prepared = generic::get_env_bool(args.prepared, &String::from("PGTPSPREPARED"));
         args.transactional =
             generic::get_env_bool(args.transactional, &String::from("PGTPSTRANSACTIONAL"));
@@ -92,42 +742,459 @@ impl Params {
             &String::from("PGTPSRANGE"),
             &String::from("1:1000"),
         );
+        args.pg_stat_statements =
+            generic::get_env_bool(args.pg_stat_statements, "PGTPSPGSTATSTATEMENTS");
+        args.wait_events = generic::get_env_bool(args.wait_events, "PGTPSWAITEVENTS");
+        args.pg_stat_io = generic::get_env_bool(args.pg_stat_io, "PGTPSPGSTATIO");
+        args.pgbouncer = generic::get_env_bool(args.pgbouncer, "PGTPSPGBOUNCER");
+        args.temp_table = generic::get_env_bool(args.temp_table, "PGTPSTEMPTABLE");
+        args.without_index = generic::get_env_bool(args.without_index, "PGTPSWITHOUTINDEX");
+        args.direct_dsn = generic::get_env_str(&args.direct_dsn, "PGTPSDIRECTSOURCE", "");
+        args.pin_cpus = generic::get_env_bool(args.pin_cpus, "PGTPSPINCPUS");
+        args.notify = generic::get_env_bool(args.notify, "PGTPSNOTIFY");
+        args.wal_stress = generic::get_env_bool(args.wal_stress, "PGTPSWALSTRESS");
+        args.insert = generic::get_env_bool(args.insert, "PGTPSINSERT");
+        args.tablespace = generic::get_env_str(&args.tablespace, "PGTPSTABLESPACE", "");
+        args.reuse_table = generic::get_env_bool(args.reuse_table, "PGTPSREUSETABLE");
+        args.update_expr = generic::get_env_str(&args.update_expr, "PGTPSUPDATEEXPR", "");
+        args.call = generic::get_env_str(&args.call, "PGTPSCALL", "");
+        args.pre_connect = generic::get_env_bool(args.pre_connect, "PGTPSPRECONNECT");
+        args.param_type = generic::get_env_str(&args.param_type, "PGTPSPARAMTYPE", "oid");
+        args.isolation = generic::get_env_str(&args.isolation, "PGTPSISOLATION", "read-committed");
+        args.output_format =
+            generic::get_env_str(&args.output_format, "PGTPSOUTPUTFORMAT", "table");
+        args.optimize_for =
+            generic::get_env_str(&args.optimize_for, "PGTPSOPTIMIZEFOR", "tps-per-latency");
+        args.cost_expr = generic::get_env_str(&args.cost_expr, "PGTPSCOSTEXPR", "");
+        args.label = generic::get_env_str(&args.label, "PGTPSLABEL", "");
+        if args.tags.is_empty() {
+            // comma separated, e.g. PGTPSTAGS=env=test,disk=ssd
+            args.tags = generic::get_env_str("", "PGTPSTAGS", "")
+                .split(',')
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| tag.to_string())
+                .collect();
+        }
+        args.textfile = generic::get_env_str(&args.textfile, "PGTPSTEXTFILE", "");
+        args.openmetrics = generic::get_env_bool(args.openmetrics, "PGTPSOPENMETRICS");
+        args.exemplars = generic::get_env_bool(args.exemplars, "PGTPSEXEMPLARS");
+        args.steps_file = generic::get_env_str(&args.steps_file, "PGTPSSTEPSFILE", "");
+        args.json = generic::get_env_str(&args.json, "PGTPSJSON", "");
+        args.msgpack = generic::get_env_str(&args.msgpack, "PGTPSMSGPACK", "");
+        args.measure_rtt = generic::get_env_bool(args.measure_rtt, "PGTPSMEASURERTT");
         args.max_wait = generic::get_env_str(&args.max_wait, "PGTPSMAXWAIT", "10s");
+        args.connect_timeout =
+            generic::get_env_str(&args.connect_timeout, "PGTPSCONNECTTIMEOUT", "10s");
+        args.keepalives_idle = generic::get_env_u32(args.keepalives_idle, "PGTPSKEEPALIVESIDLE", 0);
+        args.keepalives_interval =
+            generic::get_env_u32(args.keepalives_interval, "PGTPSKEEPALIVESINTERVAL", 0);
+        args.max_refused = generic::get_env_opt_f64(args.max_refused, "PGTPSMAXREFUSED");
+        args.find_limit = generic::get_env_bool(args.find_limit, "PGTPSFINDLIMIT");
+        args.max_errors = generic::get_env_opt_f64(args.max_errors, "PGTPSMAXERRORS");
+        args.stall_timeout = generic::get_env_str(&args.stall_timeout, "PGTPSSTALLTIMEOUT", "60s");
+        args.heartbeat = generic::get_env_str(&args.heartbeat, "PGTPSHEARTBEAT", "0s");
+        args.max_idle_in_transaction = generic::get_env_str(
+            &args.max_idle_in_transaction,
+            "PGTPSMAXIDLEINTRANSACTION",
+            "1s",
+        );
+        args.trim_outliers = generic::get_env_f64(args.trim_outliers, "PGTPSTRIMOUTLIERS", 0.0);
+        args.progress = generic::get_env_bool(args.progress, "PGTPSPROGRESS");
+        args.monitor = generic::get_env_bool(args.monitor, "PGTPSMONITOR");
+        args.probe = generic::get_env_bool(args.probe, "PGTPSPROBE");
+        args.monitor_interval =
+            generic::get_env_str(&args.monitor_interval, "PGTPSMONITORINTERVAL", "1s");
+        args.progress_weighted =
+            generic::get_env_bool(args.progress_weighted, "PGTPSPROGRESSWEIGHTED");
+        args.events = generic::get_env_bool(args.events, "PGTPSEVENTS");
+        args.burst_on = generic::get_env_str(&args.burst_on, "PGTPSBURSTON", "");
+        args.transactions_per_step =
+            generic::get_env_u32(args.transactions_per_step, "PGTPSTRANSACTIONSPERSTEP", 0);
+        args.setup_file = generic::get_env_str(&args.setup_file, "PGTPSSETUPFILE", "");
+        args.teardown_file = generic::get_env_str(&args.teardown_file, "PGTPSTEARDOWNFILE", "");
+        args.think_time = generic::get_env_str(&args.think_time, "PGTPSTHINKTIME", "0s");
+        args.stagger_start = generic::get_env_bool(args.stagger_start, "PGTPSSTAGGERSTART");
+        args.target_latency = generic::get_env_str(&args.target_latency, "PGTPSTARGETLATENCY", "");
+        args.target_cpu_utilization = generic::get_env_f64(
+            args.target_cpu_utilization,
+            "PGTPSTARGETCPUUTILIZATION",
+            0.0,
+        );
+        args.cpu_probe = generic::get_env_str(&args.cpu_probe, "PGTPSCPUPROBE", "");
+        args.burst_off = generic::get_env_str(&args.burst_off, "PGTPSBURSTOFF", "0s");
         args.spread = generic::get_env_f64(args.spread, "PGTPSSPREAD", 10.0);
         args.min_samples = generic::get_env_u32(args.min_samples, "PGTPSMINSAMPLES", 10);
-        args
+        args.window = generic::get_env_u32(args.window, "PGTPSWINDOW", 0);
+        args.repeat = generic::get_env_u32(args.repeat, "PGTPSREPEAT", 1);
+        args.min_tps = generic::get_env_f64(args.min_tps, "PGTPSMINTPS", 0.0);
+        args.max_background_tps =
+            generic::get_env_f64(args.max_background_tps, "PGTPSMAXBACKGROUNDTPS", 0.0);
+        args.rollback_ratio = generic::get_env_f64(args.rollback_ratio, "PGTPSROLLBACKRATIO", 0.0);
+        args.regression_threshold =
+            generic::get_env_opt_f64(args.regression_threshold, "PGTPSREGRESSIONTHRESHOLD");
+        args.batch_commit = generic::get_env_u32(args.batch_commit, "PGTPSBATCHCOMMIT", 1);
+        args.prepared_variants =
+            generic::get_env_u32(args.prepared_variants, "PGTPSPREPAREDVARIANTS", 1);
+        args.count_statements =
+            generic::get_env_bool(args.count_statements, "PGTPSCOUNTSTATEMENTS");
+        args.shuffle = generic::get_env_bool(args.shuffle, "PGTPSSHUFFLE");
+        args.count_rows = generic::get_env_bool(args.count_rows, "PGTPSCOUNTROWS");
+        args.max_ramp_rate = generic::get_env_u32(args.max_ramp_rate, "PGTPSMAXRAMPRATE", 0);
+        args.batch_stats = generic::get_env_bool(args.batch_stats, "PGTPSBATCHSTATS");
+        args.cold_latency = generic::get_env_bool(args.cold_latency, "PGTPSCOLDLATENCY");
+        args.initial_batch = generic::get_env_u32(args.initial_batch, "PGTPSINITIALBATCH", 100);
+        args.fixed_batch = generic::get_env_u32(args.fixed_batch, "PGTPSFIXEDBATCH", 0);
+        args.transactions_per_session = generic::get_env_u32(
+            args.transactions_per_session,
+            "PGTPSTRANSACTIONSPERSESSION",
+            0,
+        );
+        // a query file that cannot be read (or is empty) stops the run before it connects
+        args.as_query()?;
+        Ok(args)
+    }
+    pub fn as_output_format(&self) -> Result<OutputFormat, PgTpsError> {
+        OutputFormat::from_string(&self.output_format)
+    }
+    pub fn as_objective(&self) -> Result<Objective, PgTpsError> {
+        Objective::new(&self.optimize_for, &self.cost_expr)
+    }
+    pub fn as_dsn(&self) -> Result<Dsn, PgTpsError> {
+        Ok(Dsn::from_service(&self.service, self.dsn.as_str())?
+            .with_keepalives(self.keepalives_idle, self.keepalives_interval))
+    }
+    // The DSN for everything that should not go through a connection pooler
+    pub fn as_direct_dsn(&self) -> Result<Dsn, PgTpsError> {
+        match self.direct_dsn.is_empty() {
+            true => self.as_dsn(),
+            false => Ok(Dsn::from_service(&self.service, self.direct_dsn.as_str())?
+                .with_keepalives(self.keepalives_idle, self.keepalives_interval)),
+        }
     }
-    pub fn as_dsn(&self) -> Dsn {
-        Dsn::from_string(self.dsn.as_str())
+    // Whether clients run query (or the query from query_file) instead of the built in update
+    fn custom_query(&self) -> bool {
+        !self.query.is_empty() || !self.query_file.is_empty()
     }
-    pub fn as_workload(&self) -> Workload {
-        Workload::new(
-            self.as_dsn(),
-            self.query.to_string(),
+    // The query from query_file, which cannot be combined with query and cannot be empty
+    fn as_query(&self) -> Result<String, PgTpsError> {
+        if self.query_file.is_empty() {
+            return Ok(self.query.to_string());
+        }
+        if !self.query.is_empty() {
+            return Err(PgTpsError::Config(
+                "query cannot be combined with query_file".to_string(),
+            ));
+        }
+        let path = shellexpand::tilde(&self.query_file).to_string();
+        let query = std::fs::read_to_string(&path).map_err(|err| {
+            PgTpsError::Config(format!(
+                "invalid value for query_file: {} cannot be read: {}",
+                self.query_file, err
+            ))
+        })?;
+        if query.trim().is_empty() {
+            return Err(PgTpsError::Config(format!(
+                "invalid value for query_file: {} is empty",
+                self.query_file
+            )));
+        }
+        Ok(query.trim().to_string())
+    }
+    pub fn as_workload(&self) -> Result<Workload, PgTpsError> {
+        let mut workload = Workload::new(
+            self.as_dsn()?,
+            self.as_query()?,
             self.transactional,
             self.prepared,
-        )
-    }
-    pub fn as_max_wait(&self) -> chrono::Duration {
-        match DurationString::from_string(self.max_wait.clone()) {
-            Ok(ds) => match chrono::Duration::from_std(ds.into()) {
-                Ok(duration) => duration,
-                Err(_) => panic!(
-                    "invalid value for max_wait: {} is not a Duration",
-                    self.max_wait
-                ),
-            },
-            Err(_) => panic!(
-                "invalid value for max_wait: {} is not a Duration",
-                self.max_wait
-            ),
+        );
+        workload.transactions_per_session = self.transactions_per_session;
+        workload.notify = self.notify;
+        workload.pin_cpus = self.pin_cpus;
+        workload.pgbouncer = self.pgbouncer;
+        workload.direct_dsn = self.as_direct_dsn()?;
+        workload.param_type = ParamType::from_string(&self.param_type)?;
+        if !(0.0..=100.0).contains(&self.rollback_ratio) {
+            return Err(PgTpsError::Config(format!(
+                "invalid value for rollback_ratio: {} is not a percentage",
+                self.rollback_ratio
+            )));
+        }
+        workload.rollback_ratio = self.rollback_ratio;
+        workload.isolation = Isolation::from_string(&self.isolation)?;
+        workload.initial_batch = self.initial_batch;
+        workload.batch_commit = self.batch_commit;
+        if self.prepared_variants > 1 && !workload.prepared_statements() {
+            // without prepared statements there is nothing to vary
+            return Err(PgTpsError::Config(
+                "prepared_variants requires prepared (and cannot be combined with pgbouncer)"
+                    .to_string(),
+            ));
+        }
+        workload.prepared_variants = self.prepared_variants.max(1);
+        workload.count_statements = self.count_statements;
+        workload.shuffle = self.shuffle;
+        workload.fixed_batch = self.fixed_batch;
+        if self.temp_table && self.pgbouncer {
+            // a temp table lives in a session, which transaction pooling does not give us
+            return Err(PgTpsError::Config(
+                "temp_table cannot be combined with pgbouncer".to_string(),
+            ));
+        }
+        workload.temp_table = self.temp_table;
+        if self.wal_stress && (self.temp_table || self.notify || self.custom_query()) {
+            // temp tables are not wal logged, and notify and a query are workloads of their own
+            return Err(PgTpsError::Config(
+                "wal_stress cannot be combined with temp_table, notify, query or query_file"
+                    .to_string(),
+            ));
+        }
+        workload.wal_stress = self.wal_stress;
+        if self.insert && (self.wal_stress || self.temp_table || self.notify || self.custom_query())
+        {
+            // every worker appends to the shared table, and the others are workloads of their own
+            return Err(PgTpsError::Config(
+                "insert cannot be combined with wal_stress, temp_table, notify, query or query_file"
+                    .to_string(),
+            ));
+        }
+        workload.insert = self.insert;
+        workload.tablespace = self.tablespace.clone();
+        if self.reuse_table && self.temp_table {
+            // a temp table never outlives the session that created it
+            return Err(PgTpsError::Config(
+                "reuse_table cannot be combined with temp_table".to_string(),
+            ));
+        }
+        workload.reuse_table = self.reuse_table;
+        workload.update_expr = as_update_expr(&self.update_expr);
+        if !workload.update_expr.is_empty()
+            && (self.wal_stress || self.insert || self.notify || self.custom_query())
+        {
+            // those workloads do not run the built in update
+            return Err(PgTpsError::Config(
+                "update_expr cannot be combined with wal_stress, insert, notify, query or query_file"
+                    .to_string(),
+            ));
+        }
+        workload.call = as_call(&self.call);
+        if !workload.call.is_empty() && self.transactional {
+            // a procedure that commits cannot run inside a transaction block (invalid transaction termination)
+            return Err(PgTpsError::Config(
+                "call cannot be combined with transactional".to_string(),
+            ));
+        }
+        if !workload.call.is_empty()
+            && (self.wal_stress
+                || self.insert
+                || self.notify
+                || self.custom_query()
+                || !workload.update_expr.is_empty())
+        {
+            // those are workloads of their own
+            return Err(PgTpsError::Config(
+                "call cannot be combined with wal_stress, insert, notify, query, query_file or update_expr"
+                    .to_string(),
+            ));
+        }
+        if self.exemplars && !self.openmetrics {
+            // the Prometheus text format has no exemplars
+            return Err(PgTpsError::Config(
+                "exemplars requires openmetrics".to_string(),
+            ));
+        }
+        workload.exemplars = self.exemplars;
+        workload.without_index = self.without_index;
+        workload.think_time = as_duration("think_time", &self.think_time)?
+            .to_std()
+            .map_err(|_| {
+                PgTpsError::Config(format!(
+                    "invalid value for think_time: {} is negative",
+                    self.think_time
+                ))
+            })?;
+        workload.stagger_start = self.stagger_start;
+        Ok(workload)
+    }
+    // The size of the sliding window of samples that is checked for spread
+    pub fn as_window(&self) -> usize {
+        match self.window {
+            0 => self.min_samples as usize + 1,
+            window => window as usize,
+        }
+    }
+    pub fn as_max_wait(&self) -> Result<chrono::Duration, PgTpsError> {
+        as_duration("max_wait", &self.max_wait)
+    }
+    // 0 stops on the first refused connection, so only not setting it at all means the default
+    pub fn as_max_refused(&self) -> f64 {
+        self.max_refused.unwrap_or(50.0)
+    }
+    // and 0 stops on the first failing transaction
+    pub fn as_max_errors(&self) -> f64 {
+        self.max_errors.unwrap_or(1.0)
+    }
+    // and 0 makes any drop in TPS or rise in latency a regression
+    pub fn as_regression_threshold(&self) -> f64 {
+        self.regression_threshold.unwrap_or(5.0)
+    }
+    pub fn as_connect_timeout(&self) -> Result<chrono::Duration, PgTpsError> {
+        as_duration("connect_timeout", &self.connect_timeout)
+    }
+    pub fn as_stall_timeout(&self) -> Result<chrono::Duration, PgTpsError> {
+        as_duration("stall_timeout", &self.stall_timeout)
+    }
+    pub fn as_heartbeat(&self) -> Result<chrono::Duration, PgTpsError> {
+        as_duration("heartbeat", &self.heartbeat)
+    }
+    pub fn as_max_idle_in_transaction(&self) -> Result<chrono::Duration, PgTpsError> {
+        as_duration("max_idle_in_transaction", &self.max_idle_in_transaction)
+    }
+    // a zero interval would keep the server busy sampling
+    pub fn as_monitor_interval(&self) -> Result<chrono::Duration, PgTpsError> {
+        let interval = as_duration("monitor_interval", &self.monitor_interval)?;
+        if interval <= chrono::Duration::zero() {
+            return Err(PgTpsError::Config(format!(
+                "invalid value for monitor_interval: {} is not above 0",
+                self.monitor_interval
+            )));
+        }
+        Ok(interval)
+    }
+    // below 50, as trimming the top and the bottom half would leave nothing
+    pub fn as_trim_outliers(&self) -> Result<f64, PgTpsError> {
+        if !(0.0..50.0).contains(&self.trim_outliers) {
+            return Err(PgTpsError::Config(format!(
+                "invalid value for trim_outliers: {} is not a percentage below 50",
+                self.trim_outliers
+            )));
+        }
+        Ok(self.trim_outliers)
+    }
+    // the latency to search the number of clients for, or None when running a sweep
+    pub fn as_target_latency(&self) -> Result<Option<chrono::Duration>, PgTpsError> {
+        if self.target_latency.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(as_duration("target_latency", &self.target_latency)?))
+    }
+    // the CPU utilization to search the number of clients for and the command that probes it, or None
+    pub fn as_target_cpu_utilization(&self) -> Result<Option<(f64, String)>, PgTpsError> {
+        if self.target_cpu_utilization == 0.0 {
+            return Ok(None);
+        }
+        if !(0.0..=100.0).contains(&self.target_cpu_utilization) {
+            return Err(PgTpsError::Config(format!(
+                "invalid value for target_cpu_utilization: {} is not a percentage",
+                self.target_cpu_utilization
+            )));
+        }
+        if self.cpu_probe.is_empty() {
+            return Err(PgTpsError::Config(
+                "target_cpu_utilization requires cpu_probe".to_string(),
+            ));
+        }
+        if !self.target_latency.is_empty() {
+            return Err(PgTpsError::Config(
+                "target_cpu_utilization cannot be combined with target_latency".to_string(),
+            ));
         }
+        Ok(Some((self.target_cpu_utilization, self.cpu_probe.clone())))
     }
+    // burst on and burst off durations, or None when not running in bursts
+    pub fn as_burst(&self) -> Result<Option<(chrono::Duration, chrono::Duration)>, PgTpsError> {
+        if self.burst_on.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((
+            as_duration("burst_on", &self.burst_on)?,
+            as_duration("burst_off", &self.burst_off)?,
+        )))
+    }
+    // The number of transactions every step runs, or None when a step runs until it is stable
+    pub fn as_transactions_per_step(&self) -> Result<Option<u64>, PgTpsError> {
+        if self.transactions_per_step == 0 {
+            return Ok(None);
+        }
+        // those stop a step on their own terms
+        let target_cpu_utilization = match self.target_cpu_utilization > 0.0 {
+            true => self.target_cpu_utilization.to_string(),
+            false => "".to_string(),
+        };
+        for (name, value) in [
+            ("burst_on", &self.burst_on),
+            ("target_latency", &self.target_latency),
+            ("target_cpu_utilization", &target_cpu_utilization),
+        ] {
+            if !value.is_empty() {
+                return Err(PgTpsError::Config(format!(
+                    "transactions_per_step cannot be combined with {}",
+                    name
+                )));
+            }
+        }
+        Ok(Some(self.transactions_per_step as u64))
+    }
+    // All tags (and the label as tag "label"), sorted by key
+    pub fn as_tags(&self) -> Result<BTreeMap<String, String>, PgTpsError> {
+        let key_re = regex::Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
+        let mut tags = BTreeMap::new();
+        for tag in self.tags.iter() {
+            match tag.split_once('=') {
+                // keys are used as label names in the textfile, so they need to be valid as such
+                Some((key, value))
+                    if key_re.is_match(key) && !["clients", "host", "label", "target"].contains(&key) =>
+                {
+                    tags.insert(key.to_string(), value.to_string());
+                }
+                _ => {
+                    return Err(PgTpsError::Config(format!(
+                        "invalid value for tag: {} is not key=value with a key of letters, digits and _ (other than clients, host, label and target)",
+                        tag
+                    )))
+                }
+            }
+        }
+        if !self.label.is_empty() {
+            tags.insert("label".to_string(), self.label.to_string());
+        }
+        Ok(tags)
+    }
+    // The numbers of clients of every step in the sweep
+    pub fn as_steps(&self) -> Result<Vec<u32>, PgTpsError> {
+        if self.steps_file.is_empty() {
+            let (min_threads, max_threads) = self.range_min_max();
+            let mut steps: Vec<u32> = Fibonacci::starting_at(min_threads)
+                .take_while(|v| *v < max_threads)
+                .collect();
+            // the sweep starts at min, also when that is not a fibonacci number
+            if min_threads > 0 && min_threads < max_threads && steps.first() != Some(&min_threads) {
+                steps.insert(0, min_threads);
+            }
+            return Ok(steps);
+        }
+        let path = shellexpand::tilde(&self.steps_file).to_string();
+        let steps = parse_steps(&std::fs::read_to_string(path)?)?;
+        if self.notify && steps.first() == Some(&1) {
+            return Err(PgTpsError::Config(format!(
+                "invalid value for steps_file: {} starts at 1 client, and notify needs at least 2",
+                self.steps_file
+            )));
+        }
+        Ok(steps)
+    }
+    // With notify the sweep starts at 2 clients, since half of them listen (and worker 0 always notifies)
     pub fn range_min_max(&self) -> (u32, u32) {
+        let (min_threads, max_threads) = self.range_values();
+        match self.notify {
+            true => (min_threads.max(2), max_threads),
+            false => (min_threads, max_threads),
+        }
+    }
+    fn range_values(&self) -> (u32, u32) {
         let re = regex::Regex::new(r"\d+").unwrap();
         let values: Vec<_> = re
             .find_iter(self.range.as_str())
-            .filter_map(|digits| (digits.as_str().parse().ok()))
+            .filter_map(|digits| digits.as_str().parse().ok())
             .collect();
         match values.len() {
             0 => (1, 1000),
@@ -136,3 +1203,205 @@ impl Params {
         }
     }
 }
+
+// One number of clients per line, skipping blank lines and # comments.
+// Clients are only ever added, so every step needs more clients than the one before.
+fn parse_steps(contents: &str) -> Result<Vec<u32>, PgTpsError> {
+    let mut steps: Vec<u32> = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.parse::<u32>() {
+            Ok(step) if step > *steps.last().unwrap_or(&0) => steps.push(step),
+            _ => {
+                return Err(PgTpsError::Config(format!(
+                    "invalid value for steps_file: line {} ({}) is not a number of clients above the previous one",
+                    i + 1,
+                    line
+                )))
+            }
+        }
+    }
+    if steps.is_empty() {
+        return Err(PgTpsError::Config(
+            "invalid value for steps_file: it has no steps".to_string(),
+        ));
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_steps() {
+        let steps = |range: &str| {
+            Params::from_iter(["pg_tps_optimizer", "--range", range])
+                .as_steps()
+                .unwrap()
+        };
+        assert_eq!(steps("100:200"), vec![100, 144]);
+        assert_eq!(steps("89:200"), vec![89, 144]);
+        assert_eq!(steps("1:10"), vec![1, 2, 3, 5, 8]);
+        assert_eq!(steps("10:10"), Vec::<u32>::new());
+        // with notify the sweep starts at 2 clients, since 1 client would not listen
+        let params = Params::from_iter(["pg_tps_optimizer", "--range", "1:10", "--notify"]);
+        assert_eq!(params.as_steps().unwrap(), vec![2, 3, 5, 8]);
+        let tmp_dir = std::env::temp_dir().join("pg_tps_optimizer_test_as_steps");
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let steps_file = tmp_dir.join("steps");
+        std::fs::write(&steps_file, "1\n2\n").unwrap();
+        let params = Params::from_iter([
+            "pg_tps_optimizer",
+            "--steps-file",
+            steps_file.to_str().unwrap(),
+            "--notify",
+        ]);
+        assert!(matches!(params.as_steps(), Err(PgTpsError::Config(_))));
+        std::fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_as_max_refused() {
+        assert_eq!(
+            Params::from_iter(["pg_tps_optimizer"]).as_max_refused(),
+            50.0
+        );
+        let params = Params::from_iter(["pg_tps_optimizer", "--max-refused", "0"]);
+        assert_eq!(params.as_max_refused(), 0.0);
+    }
+
+    #[test]
+    fn test_as_max_errors() {
+        assert_eq!(Params::from_iter(["pg_tps_optimizer"]).as_max_errors(), 1.0);
+        let params = Params::from_iter(["pg_tps_optimizer", "--max-errors", "0"]);
+        assert_eq!(params.as_max_errors(), 0.0);
+    }
+
+    #[test]
+    fn test_as_regression_threshold() {
+        let params = Params::from_iter(["pg_tps_optimizer"]);
+        assert_eq!(params.as_regression_threshold(), 5.0);
+        let params = Params::from_iter(["pg_tps_optimizer", "--regression-threshold", "0"]);
+        assert_eq!(params.as_regression_threshold(), 0.0);
+    }
+
+    #[test]
+    fn test_as_update_expr() {
+        assert_eq!(as_update_expr(""), "");
+        assert_eq!(
+            as_update_expr("counter = counter + 1"),
+            "counter = counter + 1"
+        );
+        assert_eq!(as_update_expr(" SET  counter = 0 "), "counter = 0");
+        assert_eq!(as_update_expr("settled = true"), "settled = true");
+    }
+
+    #[test]
+    fn test_as_call() {
+        assert_eq!(as_call(""), "");
+        assert_eq!(as_call("my_proc($1)"), "my_proc($1)");
+        assert_eq!(as_call(" CALL  my_proc($1); "), "my_proc($1)");
+        assert_eq!(as_call("caller_proc()"), "caller_proc()");
+        let params = Params::from_iter(["pg_tps_optimizer", "--call", "my_proc($1)", "-t"]);
+        assert!(matches!(params.as_workload(), Err(PgTpsError::Config(_))));
+        let params = Params::from_iter(["pg_tps_optimizer", "--call", "my_proc($1)", "--insert"]);
+        assert!(matches!(params.as_workload(), Err(PgTpsError::Config(_))));
+    }
+
+    #[test]
+    fn test_prepared_variants() {
+        // the defaults that get_args would fill in from the environment
+        let variants = |args: &[&str]| {
+            let defaults = [
+                "--param-type",
+                "oid",
+                "--isolation",
+                "read-committed",
+                "--think-time",
+                "0s",
+            ];
+            let params = Params::from_iter(
+                ["pg_tps_optimizer"]
+                    .iter()
+                    .chain(defaults.iter())
+                    .chain(args.iter()),
+            );
+            params.as_workload().map(|w| w.prepared_variants)
+        };
+        assert_eq!(variants(&[]).unwrap(), 1);
+        assert_eq!(variants(&["-p", "--prepared-variants", "4"]).unwrap(), 4);
+        // there is nothing to vary without prepared statements
+        assert!(matches!(
+            variants(&["--prepared-variants", "4"]),
+            Err(PgTpsError::Config(msg)) if msg.starts_with("prepared_variants")
+        ));
+        assert!(matches!(
+            variants(&["-p", "--pgbouncer", "--prepared-variants", "4"]),
+            Err(PgTpsError::Config(msg)) if msg.starts_with("prepared_variants")
+        ));
+    }
+
+    #[test]
+    fn test_as_query() {
+        let path =
+            std::env::temp_dir().join(format!("pg_tps_optimizer_query_{}", std::process::id()));
+        let file = path.to_str().unwrap();
+        std::fs::write(&path, "select *\nfrom pg_class\n").unwrap();
+        let params = Params::from_iter(["pg_tps_optimizer", "--query-file", file]);
+        assert_eq!(params.as_query().unwrap(), "select *\nfrom pg_class");
+        let both = Params::from_iter([
+            "pg_tps_optimizer",
+            "--query-file",
+            file,
+            "--query",
+            "select 1",
+        ]);
+        assert!(both.as_query().is_err());
+        // a query on the command line runs instead of the built in update too
+        let workload = |args: &[&str]| {
+            let defaults = [
+                "--param-type",
+                "oid",
+                "--isolation",
+                "read-committed",
+                "--think-time",
+                "0s",
+            ];
+            Params::from_iter(
+                ["pg_tps_optimizer"]
+                    .iter()
+                    .chain(defaults.iter())
+                    .chain(args.iter()),
+            )
+            .as_workload()
+        };
+        assert_eq!(
+            workload(&["--query", "select 1"]).unwrap().custom_query(),
+            Some("select 1")
+        );
+        assert_eq!(workload(&[]).unwrap().custom_query(), None);
+        assert!(matches!(
+            workload(&["--query", "select 1", "--insert"]),
+            Err(PgTpsError::Config(_))
+        ));
+        std::fs::write(&path, "\n").unwrap();
+        assert!(params.as_query().is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(params.as_query().is_err());
+    }
+
+    #[test]
+    fn test_parse_steps() {
+        let steps = parse_steps("# clients\n1\n\n10 # ten\n  100\n").unwrap();
+        assert_eq!(steps, vec![1, 10, 100]);
+        assert!(parse_steps("# nothing\n\n").is_err());
+        assert!(parse_steps("10\n5\n").is_err());
+        assert!(parse_steps("10\n10\n").is_err());
+        assert!(parse_steps("0\n").is_err());
+        assert!(parse_steps("ten\n").is_err());
+    }
+}
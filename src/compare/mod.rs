@@ -0,0 +1,260 @@
+/*
+Compare reads the json documents (see --json) of two earlier runs, and reports the change in
+(median) TPS and latency per target and number of clients (see --compare).
+A drop in TPS or a rise in latency of more than the threshold is a regression,
+which makes the run fail, so that it can be used as a gate in CI.
+*/
+use crate::error::PgTpsError;
+use crate::json::SCHEMA_VERSION;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// The median tps and latency (usec) per target and number of clients
+type Medians = BTreeMap<(String, u64), (f64, f64)>;
+
+pub struct Delta {
+    pub target: String,
+    pub clients: u64,
+    // before and after
+    pub tps: (f64, f64),
+    pub latency: (f64, f64),
+}
+
+// The change from before to after in percent, where going from 0 to anything else is an infinite change
+fn percent_change(before: f64, after: f64) -> f64 {
+    match (before == 0.0, after == 0.0) {
+        (true, true) => 0.0,
+        (true, false) => f64::INFINITY.copysign(after),
+        _ => 100.0 * (after - before) / before,
+    }
+}
+
+impl Delta {
+    pub fn tps_change(&self) -> f64 {
+        percent_change(self.tps.0, self.tps.1)
+    }
+    pub fn latency_change(&self) -> f64 {
+        percent_change(self.latency.0, self.latency.1)
+    }
+    // less tps or more latency (in percent) than threshold allows
+    pub fn regression(&self, threshold: f64) -> bool {
+        self.tps_change() < -threshold || self.latency_change() > threshold
+    }
+}
+
+// A json document as written by --json, with a schema_version we understand
+pub fn load(path: &str) -> Result<Value, PgTpsError> {
+    let contents = std::fs::read_to_string(path).map_err(|err| {
+        PgTpsError::Config(format!(
+            "invalid value for compare: {} cannot be read: {}",
+            path, err
+        ))
+    })?;
+    let document: Value = serde_json::from_str(&contents).map_err(|err| {
+        PgTpsError::Config(format!(
+            "invalid value for compare: {} is not a json document: {}",
+            path, err
+        ))
+    })?;
+    if document["schema_version"] != SCHEMA_VERSION {
+        return Err(PgTpsError::Config(format!(
+            "invalid value for compare: {} has schema_version {}, expected {}",
+            path, document["schema_version"], SCHEMA_VERSION
+        )));
+    }
+    Ok(document)
+}
+
+fn medians(document: &Value) -> Medians {
+    let mut medians = Medians::new();
+    if let Some(results) = document["results"].as_array() {
+        for result in results {
+            let target = format!(
+                "{}/{}",
+                result["host"].as_str().unwrap_or_default(),
+                result["dbname"].as_str().unwrap_or_default()
+            );
+            let clients = result["clients"].as_u64().unwrap_or_default();
+            medians.insert(
+                (target, clients),
+                (
+                    result["tps"]["median"].as_f64().unwrap_or_default(),
+                    result["latency_usec"]["median"]
+                        .as_f64()
+                        .unwrap_or_default(),
+                ),
+            );
+        }
+    }
+    medians
+}
+
+// The number of clients only, when both documents hold a single target (e.g. before and after on another server)
+fn single_target(medians: &Medians) -> bool {
+    let mut targets = medians.keys().map(|(target, _)| target);
+    match targets.next() {
+        Some(first) => targets.all(|target| target == first),
+        None => false,
+    }
+}
+
+// Per target and number of clients that both documents have results for, the change from before to after
+pub fn compare(before: &Value, after: &Value) -> Vec<Delta> {
+    let (before, after) = (medians(before), medians(after));
+    let by_clients = single_target(&before) && single_target(&after);
+    let mut deltas = Vec::new();
+    for ((target, clients), (tps_before, latency_before)) in before.iter() {
+        let matched = after.iter().find(|((other, other_clients), _)| {
+            other_clients == clients && (by_clients || other == target)
+        });
+        if let Some((_, (tps_after, latency_after))) = matched {
+            deltas.push(Delta {
+                target: target.to_string(),
+                clients: *clients,
+                tps: (*tps_before, *tps_after),
+                latency: (*latency_before, *latency_after),
+            });
+        }
+    }
+    deltas
+}
+
+// Print the change from before to after, and fail when anything regressed more than threshold (percent)
+pub fn run(before: &str, after: &str, threshold: f64) -> Result<(), PgTpsError> {
+    let deltas = compare(&load(before)?, &load(after)?);
+    if deltas.is_empty() {
+        return Err(PgTpsError::Config(format!(
+            "invalid value for compare: {} and {} have no number of clients in common",
+            before, after
+        )));
+    }
+    println!(
+        "Comparing {} (before) with {} (after), regression threshold {}%:",
+        before, after, threshold
+    );
+    println!(
+        "| {:<30} | {:>7} | {:>12} | {:>12} | {:>8} | {:>12} | {:>12} | {:>8} |            |",
+        "Target",
+        "Clients",
+        "TPS before",
+        "TPS after",
+        "change",
+        "usec before",
+        "usec after",
+        "change"
+    );
+    let mut regressions = 0;
+    for delta in deltas.iter() {
+        let regression = delta.regression(threshold);
+        if regression {
+            regressions += 1;
+        }
+        println!(
+            "| {:<30} | {:>7} | {:>12.3} | {:>12.3} | {:>7.2}% | {:>12.1} | {:>12.1} | {:>7.2}% | {:<10} |",
+            delta.target,
+            delta.clients,
+            delta.tps.0,
+            delta.tps.1,
+            delta.tps_change(),
+            delta.latency.0,
+            delta.latency.1,
+            delta.latency_change(),
+            match regression {
+                true => "REGRESSION",
+                false => "",
+            }
+        );
+    }
+    match regressions {
+        0 => Ok(()),
+        _ => Err(PgTpsError::Regression(format!(
+            "{} of {} results changed more than {}%",
+            regressions,
+            deltas.len(),
+            threshold
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document(host: &str, results: &[(u64, f64, f64)]) -> Value {
+        json!({
+            "schema_version": SCHEMA_VERSION,
+            "results": results.iter().map(|(clients, tps, latency)| json!({
+                "host": host,
+                "dbname": "postgres",
+                "clients": clients,
+                "tps": {"median": tps},
+                "latency_usec": {"median": latency},
+            })).collect::<Vec<Value>>(),
+        })
+    }
+
+    #[test]
+    fn test_compare() {
+        let before = document(
+            "db1",
+            &[
+                (1, 1000.0, 1000.0),
+                (2, 1800.0, 1100.0),
+                (3, 2000.0, 1500.0),
+            ],
+        );
+        // another server, but a single target in both, so they are compared by clients only
+        let after = document(
+            "db2",
+            &[
+                (2, 1700.0, 1100.0),
+                (3, 2100.0, 1800.0),
+                (5, 2200.0, 2200.0),
+            ],
+        );
+        let deltas = compare(&before, &after);
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].clients, 2);
+        assert!((deltas[0].tps_change() + 5.555).abs() < 0.01);
+        assert_eq!(deltas[0].latency_change(), 0.0);
+        assert!(deltas[0].regression(5.0));
+        assert!(!deltas[0].regression(10.0));
+        // more tps, but latency went up 20%
+        assert_eq!(deltas[1].latency_change(), 20.0);
+        assert!(deltas[1].regression(10.0));
+        assert!(!deltas[1].regression(25.0));
+    }
+
+    #[test]
+    fn test_compare_from_zero() {
+        let before = document("db1", &[(1, 0.0, 0.0), (2, 1000.0, 1000.0), (3, 0.0, 0.0)]);
+        let after = document("db1", &[(1, 1000.0, 1000.0), (2, 0.0, 0.0), (3, 0.0, 0.0)]);
+        let deltas = compare(&before, &after);
+        // latency from 0 to something is a regression, whatever the threshold
+        assert_eq!(deltas[0].latency_change(), f64::INFINITY);
+        assert!(deltas[0].regression(1000.0));
+        // and tps from something to 0 too
+        assert_eq!(deltas[1].tps_change(), -100.0);
+        assert!(deltas[1].regression(5.0));
+        // nothing before and after is no change
+        assert_eq!(deltas[2].tps_change(), 0.0);
+        assert!(!deltas[2].regression(0.0));
+        // and with a threshold of 0, any change is a regression
+        let after = document("db1", &[(2, 999.0, 1000.0)]);
+        assert!(compare(&before, &after)[0].regression(0.0));
+    }
+
+    #[test]
+    fn test_load() {
+        let file = std::env::temp_dir().join("pg_tps_optimizer_test_compare.json");
+        let path = file.display().to_string();
+        std::fs::write(&file, document("db1", &[(1, 1.0, 1.0)]).to_string()).unwrap();
+        assert!(load(&path).is_ok());
+        std::fs::write(&file, json!({"schema_version": 0}).to_string()).unwrap();
+        assert!(load(&path).is_err());
+        std::fs::write(&file, "not json").unwrap();
+        assert!(load(&path).is_err());
+        assert!(load("/nonexistent/compare.json").is_err());
+    }
+}
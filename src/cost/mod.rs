@@ -0,0 +1,317 @@
+/*
+Cost picks the optimum of a sweep: the step that scores best on what the user optimizes for (see --optimize-for),
+which is the TPS, the latency, TPS per latency (as in the table), or a custom expression over the metrics of a step (see --cost-expr).
+An expression is parsed once into a tree of numbers, variables and + - * / with parentheses, and is always maximized.
+*/
+use crate::error::PgTpsError;
+
+// The variables an expression can use, as documented for --cost-expr
+const VARIABLES: [&str; 5] = [
+    "clients",
+    "tps",
+    "latency_usec",
+    "latency_ms",
+    "overhead_usec",
+];
+
+// The metrics of a step that an objective scores
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    pub clients: u32,
+    pub tps: f64,
+    pub latency_usec: f64,
+    pub overhead_usec: f64,
+}
+
+impl Metrics {
+    fn get(&self, variable: &str) -> f64 {
+        match variable {
+            "clients" => self.clients as f64,
+            "tps" => self.tps,
+            "latency_usec" => self.latency_usec,
+            "latency_ms" => self.latency_usec / 1000.0,
+            "overhead_usec" => self.overhead_usec,
+            _ => unreachable!("variables are checked when parsing"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(&'static str),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, metrics: &Metrics) -> f64 {
+        match self {
+            Expr::Number(number) => *number,
+            Expr::Variable(variable) => metrics.get(variable),
+            Expr::Negate(expr) => -expr.eval(metrics),
+            Expr::Binary(left, operator, right) => {
+                let (left, right) = (left.eval(metrics), right.eval(metrics));
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    _ => left / right,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            let number = number
+                .parse()
+                .map_err(|_| format!("{} is not a number", number))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Name(name));
+        } else {
+            return Err(format!("unexpected character {}", c));
+        }
+    }
+    Ok(tokens)
+}
+
+// A recursive descent parser, where * and / bind stronger than + and -
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next_symbol(&mut self, symbols: &str) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(c)) if symbols.contains(*c) => {
+                self.position += 1;
+                Some(*c)
+            }
+            _ => None,
+        }
+    }
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        while let Some(operator) = self.next_symbol("+-") {
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        while let Some(operator) = self.next_symbol("*/") {
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+    fn factor(&mut self) -> Result<Expr, String> {
+        if self.next_symbol("-").is_some() {
+            return Ok(Expr::Negate(Box::new(self.factor()?)));
+        }
+        if self.next_symbol("(").is_some() {
+            let expr = self.expr()?;
+            return match self.next_symbol(")") {
+                Some(_) => Ok(expr),
+                None => Err("missing )".to_string()),
+            };
+        }
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Name(name)) => match VARIABLES.iter().find(|v| **v == name) {
+                Some(variable) => Ok(Expr::Variable(variable)),
+                None => Err(format!("{} is not one of {}", name, VARIABLES.join(", "))),
+            },
+            Some(Token::Symbol(c)) => Err(format!("unexpected {}", c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+pub fn parse(expr: &str) -> Result<Expr, PgTpsError> {
+    let invalid =
+        |msg: String| PgTpsError::Config(format!("invalid value for cost_expr: {}: {}", expr, msg));
+    let mut parser = Parser {
+        tokens: tokenize(expr).map_err(invalid)?,
+        position: 0,
+    };
+    let parsed = parser.expr().map_err(invalid)?;
+    if parser.position < parser.tokens.len() {
+        return Err(invalid(format!(
+            "unexpected {:?}",
+            parser.tokens[parser.position]
+        )));
+    }
+    Ok(parsed)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Objective {
+    Tps,
+    Latency,
+    TpsPerLatency,
+    Expr(String, Expr),
+}
+
+impl Objective {
+    // A custom expression takes precedence over optimize_for
+    pub fn new(optimize_for: &str, cost_expr: &str) -> Result<Objective, PgTpsError> {
+        if !cost_expr.trim().is_empty() {
+            return Ok(Objective::Expr(
+                cost_expr.trim().to_string(),
+                parse(cost_expr)?,
+            ));
+        }
+        match optimize_for.to_lowercase().as_str() {
+            "tps" => Ok(Objective::Tps),
+            "latency" => Ok(Objective::Latency),
+            "tps-per-latency" => Ok(Objective::TpsPerLatency),
+            _ => Err(PgTpsError::Config(format!(
+                "invalid value for optimize_for: {} is not one of tps, latency or tps-per-latency",
+                optimize_for
+            ))),
+        }
+    }
+    pub fn name(&self) -> String {
+        match self {
+            Objective::Tps => "tps".to_string(),
+            Objective::Latency => "latency".to_string(),
+            Objective::TpsPerLatency => "tps-per-latency".to_string(),
+            Objective::Expr(expr, _) => expr.to_string(),
+        }
+    }
+    fn score(&self, metrics: &Metrics) -> f64 {
+        match self {
+            Objective::Tps => metrics.tps,
+            Objective::Latency => metrics.latency_usec,
+            Objective::TpsPerLatency => metrics.tps / metrics.latency_usec,
+            Objective::Expr(_, expr) => expr.eval(metrics),
+        }
+    }
+    // The number of clients of the step that scores best, with its score.
+    // Latency is minimized, everything else maximized, and steps that score no number (e.g. a division by 0) are skipped.
+    pub fn optimum(&self, steps: &[Metrics]) -> Option<(u32, f64)> {
+        let minimize = matches!(self, Objective::Latency);
+        steps
+            .iter()
+            .map(|metrics| (metrics.clients, self.score(metrics)))
+            .filter(|(_, score)| score.is_finite())
+            .reduce(
+                |best, step| match (minimize, step.1 < best.1, step.1 > best.1) {
+                    (true, true, _) | (false, _, true) => step,
+                    _ => best,
+                },
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps() -> Vec<Metrics> {
+        [
+            (1, 1000.0, 1000.0),
+            (2, 1800.0, 1100.0),
+            (3, 2000.0, 1500.0),
+        ]
+        .iter()
+        .map(|(clients, tps, latency_usec)| Metrics {
+            clients: *clients,
+            tps: *tps,
+            latency_usec: *latency_usec,
+            overhead_usec: 0.0,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let metrics = steps()[1];
+        for (expr, value) in [
+            ("tps", 1800.0),
+            ("tps - 2*latency_ms", 1797.8),
+            ("(tps - 800) / clients", 500.0),
+            ("-latency_usec + 2 * 3", -1094.0),
+            ("10 - 4 - 3", 3.0),
+            ("--clients", 2.0),
+        ] {
+            assert!(
+                (parse(expr).unwrap().eval(&metrics) - value).abs() < 1e-9,
+                "{}",
+                expr
+            );
+        }
+        for expr in [
+            "",
+            "tps +",
+            "(tps",
+            "tps)",
+            "wal",
+            "1..2",
+            "tps; drop",
+            "2 tps",
+        ] {
+            assert!(
+                matches!(parse(expr), Err(PgTpsError::Config(_))),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn test_optimum() {
+        let steps = steps();
+        let objective = |optimize_for, cost_expr| Objective::new(optimize_for, cost_expr).unwrap();
+        assert_eq!(objective("tps", "").optimum(&steps), Some((3, 2000.0)));
+        assert_eq!(objective("latency", "").optimum(&steps), Some((1, 1000.0)));
+        assert_eq!(
+            objective("Tps-Per-Latency", "").optimum(&steps),
+            Some((2, 1800.0 / 1100.0))
+        );
+        // the expression takes precedence
+        let custom = objective("latency", " tps - latency_ms ");
+        assert_eq!(custom.name(), "tps - latency_ms");
+        assert_eq!(custom.optimum(&steps), Some((3, 1998.5)));
+        assert_eq!(
+            objective("tps", "1 / (clients - 1)").optimum(&steps),
+            Some((2, 1.0))
+        );
+        assert_eq!(objective("tps", "").optimum(&[]), None);
+        assert!(Objective::new("throughput", "").is_err());
+    }
+}
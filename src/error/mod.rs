@@ -0,0 +1,57 @@
+/*
+PgTpsError describes everything that can go wrong, so that callers can tell
+a connection problem from a failing query, a configuration mistake, a failing sampler,
+a run that was interrupted (e.g. because the other side of a channel is gone),
+results that regressed compared to an earlier run (see --compare),
+or a database that is too busy to benchmark (see --max-background-tps).
+*/
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PgTpsError {
+    #[error("could not connect: {0}")]
+    Connect(String),
+    #[error("query failed: {0}")]
+    Query(#[from] postgres::Error),
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("sampling statistics failed: {0}")]
+    Sampler(#[source] postgres::Error),
+    #[error("interrupted: {0}")]
+    Interrupted(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("regression: {0}")]
+    Regression(String),
+    #[error("database is not idle: {0}")]
+    Busy(String),
+}
+
+impl<T> From<std::sync::mpsc::SendError<T>> for PgTpsError {
+    fn from(_error: std::sync::mpsc::SendError<T>) -> Self {
+        PgTpsError::Interrupted("the receiving side of the channel is gone".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pg_tps_error() {
+        let err = PgTpsError::Config("max_wait is not a Duration".to_string());
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: max_wait is not a Duration"
+        );
+        let err = PgTpsError::Busy("other activity detected".to_string());
+        assert_eq!(
+            err.to_string(),
+            "database is not idle: other activity detected"
+        );
+        let (tx, rx) = std::sync::mpsc::channel::<u32>();
+        drop(rx);
+        let err: PgTpsError = tx.send(1).unwrap_err().into();
+        assert!(matches!(err, PgTpsError::Interrupted(_)));
+    }
+}
@@ -19,6 +19,15 @@ impl Fibonacci {
     pub fn new(curr: u32, next: u32) -> Fibonacci {
         Fibonacci { curr, next }
     }
+    // Starts from the two consecutive numbers straddling min,
+    // so that the first number returned is the lowest that is not below min
+    pub fn starting_at(min: u32) -> Fibonacci {
+        let mut fib = Fibonacci::new(1, 1);
+        while fib.next < min {
+            fib.next();
+        }
+        fib
+    }
 }
 
 #[cfg(test)]
@@ -36,4 +45,24 @@ mod tests {
         assert_eq!(sum, 19);
         assert_eq!(Fibonacci::new(1, 1).take(5).last().unwrap(), 8);
     }
+
+    #[test]
+    fn test_starting_at() {
+        assert_eq!(
+            Fibonacci::starting_at(0).take(4).collect::<Vec<u32>>(),
+            vec![1, 2, 3, 5]
+        );
+        assert_eq!(
+            Fibonacci::starting_at(1).take(4).collect::<Vec<u32>>(),
+            Fibonacci::new(1, 1).take(4).collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            Fibonacci::starting_at(8).take(3).collect::<Vec<u32>>(),
+            vec![8, 13, 21]
+        );
+        assert_eq!(
+            Fibonacci::starting_at(100).take(3).collect::<Vec<u32>>(),
+            vec![144, 233, 377]
+        );
+    }
 }
@@ -23,6 +23,11 @@ pub fn get_env_f64(val: f64, env_key: &str, default: f64) -> f64 {
     }
 }
 
+// Like get_env_f64, for values where 0 is a meaningful setting: val when given, else env_key when set (and a number)
+pub fn get_env_opt_f64(val: Option<f64>, env_key: &str) -> Option<f64> {
+    val.or_else(|| env::var(env_key).ok()?.parse().ok())
+}
+
 pub fn get_env_u32(val: u32, env_key: &str, default: u32) -> u32 {
     if val != 0 {
         return val;
@@ -36,10 +41,6 @@ pub fn get_env_u32(val: u32, env_key: &str, default: u32) -> u32 {
     }
 }
 
-fn shell_expand(path: &str) -> String {
-    shellexpand::tilde(path).to_string()
-}
-
 pub fn shell_exists(path: &str) -> String {
     let path = shellexpand::tilde(path).to_string();
     if std::path::Path::new(path.as_str()).exists() {
@@ -48,13 +49,26 @@ pub fn shell_exists(path: &str) -> String {
     "".to_string()
 }
 
+// Returns the (tilde expanded) path from val, env_key or default, but only if it exists.
+// Paths that do not exist are returned as an empty string (so they are not used),
+// with a warning when they were set explicitly (val or env_key).
 pub fn get_env_path(val: &str, env_key: &str, default: &str) -> String {
-    if !val.is_empty() {
-        return shell_expand(val).to_string();
-    }
-    match env::var(env_key) {
-        Ok(env_val) => shell_expand(env_val.as_str()),
-        Err(_e) => shell_exists(default).to_string(),
+    let (path, explicit) = match (val.is_empty(), env::var(env_key)) {
+        (false, _) => (val.to_string(), true),
+        (true, Ok(env_val)) => (env_val, true),
+        (true, Err(_e)) => (default.to_string(), false),
+    };
+    let existing = shell_exists(path.as_str());
+    if explicit && existing.is_empty() && !path.is_empty() {
+        eprintln!("Warning: {} does not exist and is ignored", path);
+    }
+    existing
+}
+
+// Log details that help to debug the configuration to stderr, but only when PGTPSDEBUG is set
+pub fn debug(msg: &str) {
+    if get_env_bool(false, "PGTPSDEBUG") {
+        eprintln!("Debug: {}", msg);
     }
 }
 
@@ -93,6 +107,11 @@ fn get_bool_default(val: bool, env_key: String) -> bool {
     false
 }
 */
+// Tests that change (or depend on) HOME and the other environment variables of the ssl files
+// run one at a time, since the environment is shared by all threads of the test binary
+#[cfg(test)]
+pub static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,12 +132,50 @@ mod tests {
         assert_eq!(get_env_str("", TEST_VAR, TEST_DEFAULT), TEST_DEFAULT);
     }
     #[test]
+    fn test_get_env_path() {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        const TEST_VAR: &str = "TEST_VAR_PATH";
+        const MISSING: &str = "/this/path/does/not/exist";
+        let existing = std::env::temp_dir()
+            .join("pg_tps_optimizer_test_get_env_path")
+            .display()
+            .to_string();
+        std::fs::write(existing.as_str(), "").unwrap();
+        env::remove_var(TEST_VAR);
+        // missing paths are dropped, whether they are explicit or default
+        assert_eq!(get_env_path(MISSING, TEST_VAR, ""), "");
+        assert_eq!(get_env_path("", TEST_VAR, MISSING), "");
+        env::set_var(TEST_VAR, MISSING);
+        assert_eq!(get_env_path("", TEST_VAR, existing.as_str()), "");
+        // existing paths are kept, whether they are explicit or default
+        env::set_var(TEST_VAR, existing.as_str());
+        assert_eq!(get_env_path("", TEST_VAR, MISSING), existing);
+        env::remove_var(TEST_VAR);
+        assert_eq!(get_env_path(existing.as_str(), TEST_VAR, ""), existing);
+        assert_eq!(get_env_path("", TEST_VAR, existing.as_str()), existing);
+        // and ~ is expanded
+        let home_dir = home::home_dir().unwrap().display().to_string();
+        assert_eq!(get_env_path("~", TEST_VAR, ""), home_dir);
+        std::fs::remove_file(existing.as_str()).unwrap();
+    }
+    #[test]
+    fn test_get_env_opt_f64() {
+        const TEST_VAR: &str = "TEST_VAR_OPT_F64";
+        env::set_var(TEST_VAR, "0");
+        assert_eq!(get_env_opt_f64(None, TEST_VAR), Some(0.0));
+        assert_eq!(get_env_opt_f64(Some(5.0), TEST_VAR), Some(5.0));
+        env::remove_var(TEST_VAR);
+        // an explicit 0 is kept, rather than taken for not set
+        assert_eq!(get_env_opt_f64(Some(0.0), TEST_VAR), Some(0.0));
+        assert_eq!(get_env_opt_f64(None, TEST_VAR), None);
+    }
+    #[test]
     fn test_get_env_bool() {
         const TEST_VAR: &str = "TEST_VAR_BOOL";
         const TEST_VAL: &str = "is set";
         env::set_var(TEST_VAR, TEST_VAL);
         for val in [true, false] {
-            assert_eq!(get_env_bool(val, TEST_VAR), true);
+            assert!(get_env_bool(val, TEST_VAR));
         }
         env::remove_var(TEST_VAR);
         for val in [true, false] {
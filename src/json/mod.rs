@@ -0,0 +1,188 @@
+/*
+Json exposes the results as a single versioned document (see --json).
+The envelope is {"schema_version", "metadata", "results"}, and schema_version
+is bumped on every change that could break a consumer of the document.
+*/
+use crate::aggregate::{confidence_95, Aggregate, Summary};
+use crate::cli::Params;
+use crate::threader::TestResult;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+fn summary(summary: &Summary) -> Value {
+    json!({
+        "median": summary.median,
+        "mean": summary.mean,
+        "stddev": summary.stddev,
+    })
+}
+
+// The tool, when it started and how it was configured (without the dsn, which may hold a password)
+pub fn metadata(args: &Params, start: DateTime<Utc>) -> Value {
+    json!({
+        "tool": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("PGTPS_GIT_SHA"),
+        "start_time": start.to_rfc3339(),
+        "config": {
+            "range": args.range,
+            "spread": args.spread,
+            "min_samples": args.min_samples,
+            "window": args.as_window(),
+            "max_wait": args.max_wait,
+            "repeat": args.repeat,
+            "transactional": args.transactional,
+            "prepared": args.prepared,
+            "pgbouncer": args.pgbouncer,
+            "notify": args.notify,
+            "wal_stress": args.wal_stress,
+            "insert": args.insert,
+            "tablespace": args.tablespace,
+            "reuse_table": args.reuse_table,
+            "update_expr": args.update_expr,
+            "call": args.call,
+            "optimize_for": args.optimize_for,
+            "cost_expr": args.cost_expr,
+            "param_type": args.param_type,
+            "batch_commit": args.batch_commit,
+            "prepared_variants": args.prepared_variants,
+            "rollback_ratio": args.rollback_ratio,
+            "isolation": args.isolation,
+            "transactions_per_session": args.transactions_per_session,
+            "without_index": args.without_index,
+        },
+    })
+}
+
+// The mean of one run, with its standard deviation and 95% confidence interval over the samples
+fn interval(mean: f64, stddev: Option<f64>, samples: usize) -> Value {
+    let ci95 = stddev
+        .and_then(|stddev| confidence_95(stddev, samples))
+        .map(|half| [mean - half, mean + half]);
+    json!({
+        "mean": mean,
+        "stddev": stddev,
+        "ci95": ci95,
+    })
+}
+
+// Every run of a step: when it was measured, and the mean with error bars of tps and latency
+fn step(result: &TestResult) -> Value {
+    json!({
+        "start": result.window.map(|(start, _)| start.to_rfc3339()),
+        "end": result.window.map(|(_, end)| end.to_rfc3339()),
+        "stable": result.stable,
+        "confidence": result.confidence,
+        "samples": result.samples,
+        "tps": interval(result.tps, result.stddev.map(|(tps, _)| tps), result.samples),
+        "latency_usec": interval(
+            result.latency.num_microseconds().unwrap_or_default() as f64,
+            result
+                .stddev
+                .map(|(_, latency)| latency.num_microseconds().unwrap_or_default() as f64),
+            result.samples,
+        ),
+    })
+}
+
+// One result per target and number of clients, with the host and dbname of the target and its labels
+pub fn document(
+    metadata: Value,
+    targets: &[(BTreeMap<String, String>, String, Aggregate)],
+) -> Value {
+    let mut results: Vec<Value> = Vec::new();
+    for (labels, dbname, aggregate) in targets {
+        for (clients, tps, latency) in aggregate.summaries() {
+            results.push(json!({
+                "host": labels.get("host"),
+                "dbname": dbname,
+                "labels": labels,
+                "clients": clients,
+                "runs": tps.runs,
+                "tps": summary(&tps),
+                "latency_usec": summary(&latency),
+                "windows": aggregate
+                    .windows(clients)
+                    .iter()
+                    .map(|(start, end)| json!({
+                        "start": start.to_rfc3339(),
+                        "end": end.to_rfc3339(),
+                    }))
+                    .collect::<Vec<Value>>(),
+                "steps": aggregate
+                    .results(clients)
+                    .iter()
+                    .map(step)
+                    .collect::<Vec<Value>>(),
+            }));
+        }
+    }
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "metadata": metadata,
+        "results": results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threader::TestResult;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_document() {
+        let mut aggregate = Aggregate::new();
+        aggregate.add(
+            8,
+            &TestResult {
+                stable: true,
+                tps: 1500.0,
+                latency: Duration::microseconds(2500),
+                overhead: Duration::zero(),
+                rollback_ratio: 0.0,
+                samples: 10,
+                stddev: Some((100.0, Duration::microseconds(250))),
+                window: Some((
+                    Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+                    Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 15).unwrap(),
+                )),
+                trace: None,
+                confidence: Some(85.0),
+            },
+        );
+        let labels = BTreeMap::from([("host".to_string(), "db1".to_string())]);
+        let doc = document(
+            json!({"tool": "pg_tps_optimizer"}),
+            &[(labels, "postgres".to_string(), aggregate)],
+        );
+        assert_eq!(doc["schema_version"], SCHEMA_VERSION);
+        assert_eq!(doc["metadata"]["tool"], "pg_tps_optimizer");
+        let result = &doc["results"][0];
+        assert_eq!(result["host"], "db1");
+        assert_eq!(result["dbname"], "postgres");
+        assert_eq!(result["labels"]["host"], "db1");
+        assert_eq!(result["clients"], 8);
+        assert_eq!(result["runs"], 1);
+        assert_eq!(result["tps"]["median"], 1500.0);
+        assert_eq!(result["latency_usec"]["median"], 2500.0);
+        assert_eq!(result["windows"][0]["start"], "2026-01-02T03:04:05+00:00");
+        assert_eq!(result["windows"][0]["end"], "2026-01-02T03:04:15+00:00");
+        let step = &result["steps"][0];
+        assert_eq!(step["start"], "2026-01-02T03:04:05+00:00");
+        assert_eq!(step["samples"], 10);
+        assert_eq!(step["confidence"], 85.0);
+        assert_eq!(step["tps"]["mean"], 1500.0);
+        assert_eq!(step["tps"]["stddev"], 100.0);
+        let half = confidence_95(100.0, 10).unwrap();
+        assert_eq!(step["tps"]["ci95"][0], 1500.0 - half);
+        assert_eq!(step["tps"]["ci95"][1], 1500.0 + half);
+        assert_eq!(step["latency_usec"]["stddev"], 250.0);
+        // and it parses as a whole document
+        let parsed: Value = serde_json::from_str(&doc.to_string()).unwrap();
+        assert_eq!(parsed, doc);
+    }
+}
@@ -3,95 +3,1261 @@ extern crate chrono;
 extern crate getopts;
 extern crate postgres;
 
+mod aggregate;
 mod cli;
+mod compare;
+mod cost;
 mod dsn;
+mod error;
 mod fibonacci;
 mod generic;
+mod json;
+mod msgpack;
 mod pg_sampler;
+mod probe;
+mod progress;
+mod prometheus;
+mod report;
 mod threader;
+mod version;
 
-use crate::fibonacci::Fibonacci;
-use crate::threader::workload::Workload;
+use crate::aggregate::Aggregate;
+use crate::cli::Params;
+use crate::error::PgTpsError;
+use crate::pg_sampler::{CatchUp, IoRates, PgSampler, WaitEvents};
+use crate::threader::workload::{Isolation, Workload, APPLICATION_NAME};
+use crate::threader::{TestResult, Threader};
+use std::collections::BTreeMap;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = cli::Params::get_args();
+// number of empty queries to estimate the network round trip time (see --measure-rtt)
+const RTT_ROUND_TRIPS: u32 = 100;
+// how long the TPS of other activity is sampled at startup (see --max-background-tps)
+const BACKGROUND_SAMPLE_TIME: std::time::Duration = std::time::Duration::from_secs(2);
+// cpu utilization (percent) of the host we run on above which the clients may be the bottleneck
+const CLIENT_CPU_WARNING: f64 = 90.0;
 
+fn main() -> Result<(), PgTpsError> {
+    let mut args = cli::Params::get_args()?;
+    run(&mut args).inspect_err(|err| {
+        event(
+            &args,
+            "run_end",
+            serde_json::json!({"outcome": "error", "error": err.to_string()}),
+        );
+    })
+}
+
+fn run(args: &mut Params) -> Result<(), PgTpsError> {
+    if args.print_dsn {
+        for dsn in args.dsns.clone() {
+            args.dsn = dsn;
+            // like Workload::new does for the workers
+            let dsn = args.as_dsn()?.with_application_name(APPLICATION_NAME);
+            println!("{}", dsn.debug());
+            println!("{}", dsn.debug_uri());
+        }
+        return Ok(());
+    }
+    if args.probe {
+        // before the banner, so that the output is json only
+        return capabilities(args);
+    }
+    if let [before, after] = args.compare.as_slice() {
+        return compare::run(before, after, args.as_regression_threshold());
+    }
+    println!("{}", version::banner());
+    if args.dsns.len() > 1 && !args.direct_dsn.is_empty() {
+        return Err(PgTpsError::Config(
+            "direct_dsn cannot be combined with multiple dsns".to_string(),
+        ));
+    }
+    if args.monitor {
+        return monitor(args);
+    }
+    event(
+        args,
+        "run_start",
+        serde_json::json!({"version": version::VERSION, "targets": args.dsns.len()}),
+    );
+
+    let start = chrono::Utc::now();
+    // the results of every target, with the labels that tell them apart
+    let mut targets: Vec<(BTreeMap<String, String>, Aggregate)> = Vec::new();
+    let mut dbnames: Vec<String> = Vec::new();
+    let mut output = match args.msgpack.is_empty() {
+        true => None,
+        false => Some(msgpack::Writer::create(&args.msgpack)?),
+    };
+    for (i, dsn) in args.dsns.clone().into_iter().enumerate() {
+        args.dsn = dsn;
+        let dsn = args.as_dsn()?;
+        let mut labels = args.as_tags()?;
+        labels.insert("host".to_string(), dsn.host());
+        if args.dsns.len() > 1 {
+            let target = format!("{}/{}", dsn.host(), dsn.dbname());
+            println!("Target {} of {}: {}", i + 1, args.dsns.len(), target);
+            labels.insert("target".to_string(), target);
+        }
+        dbnames.push(dsn.dbname());
+        if let Some(writer) = output.as_mut() {
+            writer.labels = labels.clone();
+        }
+        targets.push((labels, run_target(args, &mut output)?));
+    }
+    if !args.textfile.is_empty() {
+        // all steps of all targets are done
+        let steps: usize = targets
+            .iter()
+            .map(|(_, aggregate)| aggregate.summaries().len())
+            .sum();
+        let mut text = prometheus::textfile(&targets)
+            + &prometheus::progress(&prometheus::Progress {
+                steps_total: steps,
+                steps_completed: steps,
+                phase: prometheus::Phase::Idle,
+            });
+        if args.exemplars {
+            text += &prometheus::exemplars(&targets);
+        }
+        let text = prometheus::terminate(text, args.openmetrics);
+        prometheus::write_textfile(&args.textfile, &text)?;
+        println!("Results written to {}", args.textfile);
+    }
+    if !args.json.is_empty() {
+        let results: Vec<(BTreeMap<String, String>, String, Aggregate)> = targets
+            .into_iter()
+            .zip(dbnames)
+            .map(|((labels, aggregate), dbname)| (labels, dbname, aggregate))
+            .collect();
+        let doc = json::document(json::metadata(args, start), &results);
+        prometheus::write_textfile(&args.json, &format!("{:#}\n", doc))?;
+        println!("Results written to {}", args.json);
+    }
+    println!("Finished");
+    event(args, "run_end", serde_json::json!({"outcome": "finished"}));
+    ::std::process::exit(0);
+}
+
+// Log a transition in the lifecycle of the run to stderr as a line of json (see --events)
+fn event(args: &Params, name: &str, fields: serde_json::Value) {
+    if !args.events {
+        return;
+    }
+    let mut line = serde_json::json!({
+        "event": name,
+        "time": chrono::Utc::now().to_rfc3339(),
+    });
+    if let (Some(line), serde_json::Value::Object(fields)) = (line.as_object_mut(), fields) {
+        line.extend(fields);
+    }
+    eprintln!("{}", line);
+}
+
+// Run all sweeps (see --repeat) against the dsn in args, and return the results of all of them
+fn run_target(
+    args: &Params,
+    output: &mut Option<msgpack::Writer>,
+) -> Result<Aggregate, PgTpsError> {
     println!("Initializing");
-    let (min_threads, max_threads) = args.range_min_max();
-    let w: Workload = args.as_workload();
+    let steps = args.as_steps()?;
+    let (min_threads, max_threads) = match (args.steps_file.is_empty(), steps.last()) {
+        (false, Some(last)) => (steps[0], *last),
+        _ => args.range_min_max(),
+    };
+    let w: Workload = args.as_workload()?;
     println!("{}", w.as_string());
-    let mut threader = threader::Threader::new(max_threads as usize, w);
-    let mut sampler = pg_sampler::PgSampler::new(args.as_dsn())?;
+    let mut sampler = PgSampler::new(args.as_direct_dsn()?)?;
     sampler.next()?;
-    let mut instable: bool = false;
-    let max_wait: chrono::Duration = args.as_max_wait();
+    if sampler.in_recovery {
+        println!("Connected to a standby (recovery is in progress), so wal metrics are n/a");
+    }
+    if !w.tablespace.is_empty() && !sampler.has_tablespace(&w.tablespace)? {
+        return Err(PgTpsError::Config(format!(
+            "invalid value for tablespace: {} does not exist",
+            w.tablespace
+        )));
+    }
+    if !w.temp_table {
+        // whether the clients start on a cold or a warm table changes the numbers
+        match w.reuse_table {
+            true => println!(
+                "Reusing the benchmark tables with the rows of earlier runs (a warm table)"
+            ),
+            false => {
+                println!("Truncating the benchmark tables at the start of the run (a cold table)")
+            }
+        }
+    }
+    println!("Postgres configuration:");
+    for (name, value) in sampler.settings()? {
+        println!("- {}: {}", name, value);
+    }
+    if args.max_background_tps > 0.0 {
+        // other activity would be counted in the postgres TPS and compete with the clients
+        let background_tps = sampler.background_tps(BACKGROUND_SAMPLE_TIME)? as f64;
+        println!(
+            "Background TPS (before any client runs): {:.3}",
+            background_tps
+        );
+        if background_tps > args.max_background_tps {
+            return Err(PgTpsError::Busy(format!(
+                "other activity detected, {:.3} TPS exceeds max_background_tps {}",
+                background_tps, args.max_background_tps
+            )));
+        }
+    }
+    if args.pg_stat_io && !sampler.track_io()? {
+        println!("pg_stat_io needs PostgreSQL 16 or newer, there will be no io summary");
+    }
+    let mut statements = false;
+    if args.pg_stat_statements {
+        if sampler.has_statements()? {
+            eprintln!("Warning: resetting pg_stat_statements, which clears statistics of all other workloads too");
+            event(
+                args,
+                "warning",
+                serde_json::json!({"msg": "resetting pg_stat_statements"}),
+            );
+            sampler.reset_statements()?;
+            statements = true;
+        } else {
+            println!("pg_stat_statements is not installed, there will be no statement summary");
+        }
+    }
+    let max_wait: chrono::Duration = args.as_max_wait()?;
 
     println!("min threads: {} max threads: {}", min_threads, max_threads);
     println!(
-        "max_wait: {}s, min_samples: {}, spread: {}",
+        "max_wait: {}s, min_samples: {}, window: {}, spread: {}",
         max_wait.num_seconds(),
         args.min_samples,
+        args.as_window(),
         args.spread
     );
+    let rtt = match args.measure_rtt {
+        true => {
+            let rtt = pg_sampler::measure_rtt(args.as_dsn()?, RTT_ROUND_TRIPS)?;
+            println!(
+                "network round trip time: {}usec (median of {} empty queries)",
+                rtt.num_microseconds().unwrap(),
+                RTT_ROUND_TRIPS
+            );
+            Some(rtt)
+        }
+        false => None,
+    };
+
+    if !args.setup_file.is_empty() {
+        println!("Running setup file {}", args.setup_file);
+        run_script(args, &args.setup_file)?;
+    }
+    let mut aggregate = Aggregate::new();
+    for run in 1..=args.repeat {
+        if args.repeat > 1 {
+            println!("Run {} of {}", run, args.repeat);
+        }
+        let target = match (args.as_target_latency()?, args.as_target_cpu_utilization()?) {
+            (Some(latency), _) => Some(Target::Latency(
+                latency.num_microseconds().unwrap_or(i64::MAX) as f64,
+            )),
+            (None, Some((cpu, command))) => Some(Target::Cpu(cpu, command)),
+            (None, None) => None,
+        };
+        let results = match target {
+            Some(target) => target_search(args, &w, target, max_threads, output)?,
+            None => {
+                let mut threader = new_threader(args, &w, max_threads)?;
+                let results = sweep(args, &mut threader, &mut sampler, rtt, output)?;
+                println!("Stopping, waiting for all threads to stop");
+                threader.finish();
+                results
+            }
+        };
+        for (num_threads, result) in results {
+            aggregate.add(num_threads, &result);
+        }
+    }
+    if !args.teardown_file.is_empty() {
+        println!("Running teardown file {}", args.teardown_file);
+        if let Err(err) = run_script(args, &args.teardown_file) {
+            let msg = format!("teardown file {} failed: {}", args.teardown_file, err);
+            eprintln!("Warning: {}", msg);
+            event(args, "warning", serde_json::json!({ "msg": msg }));
+        }
+    }
+    if args.repeat > 1 {
+        print_aggregate(&aggregate, args.as_output_format()?);
+    }
+    if statements {
+        println!("Top statements by total time (pg_stat_statements):");
+        let table = report::StatementsTable::new(args.as_output_format()?);
+        for line in table.header() {
+            println!("{}", line);
+        }
+        for statement in sampler.top_statements(10)? {
+            let cells = vec![
+                statement.calls.to_string(),
+                format!("{:.3}", statement.total_time),
+                format!("{:.3}", statement.mean_time()),
+                statement.query.chars().take(80).collect::<String>(),
+            ];
+            println!("{}", table.row(&cells));
+        }
+        if let Some(footer) = table.footer() {
+            println!("{}", footer);
+        }
+    }
+    Ok(aggregate)
+}
 
-    println!("|---------------------|---------|-----------------------------------------|-----------------------|");
-    println!("| Date       time     | Clients |                 Performance             |       Postgres        |");
-    println!("|                     |         |---------------|-----------|-------------|-----------|-----------|");
-    println!("|                     |         |      TPS      |  Latency  | TPS/Latency |   TPS     |    wal    |");
-    println!("|                     |         |               |   (usec)  |             |           |    kB/s   |");
-    println!("|---------------------|---------|---------------|-----------|-------------|-----------|-----------|");
+// A threader for up to max_threads workers, as configured by args
+fn new_threader(args: &Params, w: &Workload, max_threads: u32) -> Result<Threader, PgTpsError> {
+    let mut threader = Threader::new(max_threads as usize, w.clone(), args.as_stall_timeout()?);
+    threader.max_ramp_rate = args.max_ramp_rate;
+    threader.heartbeat = args.as_heartbeat()?;
+    threader.trim_outliers = args.as_trim_outliers()?;
+    Ok(threader)
+}
 
-    for num_threads in Fibonacci::new(1_u32, 1_u32).take_while(|v| *v < max_threads) {
-        if num_threads < min_threads {
-            continue;
+// Run the workload for every number of clients, print a row per step and return the results
+fn sweep(
+    args: &Params,
+    threader: &mut Threader,
+    sampler: &mut PgSampler,
+    rtt: Option<chrono::Duration>,
+    output: &mut Option<msgpack::Writer>,
+) -> Result<Vec<(u32, TestResult)>, PgTpsError> {
+    let mut instable: bool = false;
+    let mut below_min_tps: Option<(u32, f64)> = None;
+    // clients of the step, refused and new clients
+    let mut refused_at: Option<(u32, u32, u32)> = None;
+    // clients of the step, failed and total attempts (see --find-limit)
+    let mut errors_at: Option<(u32, u64, u64)> = None;
+    let connect_timeout = args.as_connect_timeout()?;
+    let max_wait: chrono::Duration = args.as_max_wait()?;
+    let burst = args.as_burst()?;
+    let transactions_per_step = args.as_transactions_per_step()?;
+    // how long every step took to run transactions_per_step
+    let mut completed: Vec<(u32, chrono::Duration)> = Vec::new();
+    let mut results: Vec<(u32, TestResult)> = Vec::new();
+    let mut connect_times: Vec<(u32, Option<chrono::Duration>)> = Vec::new();
+    let mut wait_events: Vec<(u32, WaitEvents)> = Vec::new();
+    // steps with clients that were idle in a transaction for too long, with how many and the longest (see --max-idle-in-transaction)
+    let mut idle_in_transaction: Vec<(u32, i64, chrono::Duration)> = Vec::new();
+    let max_idle_in_transaction = args.as_max_idle_in_transaction()?;
+    // io operations per second per context per step (see --pg-stat-io)
+    let mut io: Vec<(u32, Vec<IoRates>)> = Vec::new();
+    let mut rollbacks: Vec<(u32, f64, String)> = Vec::new();
+    // requested and achieved batch size and sample duration per timeslice per step (see --batch-stats)
+    let mut batches: Vec<(u32, Vec<threader::BatchStat>)> = Vec::new();
+    // cold and warm transactions per step (see --cold-latency)
+    let mut cold: Vec<(u32, threader::Totals)> = Vec::new();
+    // tps and rows affected per step (see --count-rows)
+    let mut rows: Vec<(u32, f64, threader::Totals)> = Vec::new();
+    // transactions and deadlocks per step (see --shuffle)
+    let mut deadlocks: Vec<(u32, u64, u64)> = Vec::new();
+    // wal rate and replica catch up per step (see --wal-stress)
+    let mut catch_ups: Vec<(u32, String, CatchUp)> = Vec::new();
+    // rows inserted per second and the table size, index size and rows after every step (see --insert)
+    let mut inserts: Vec<(u32, f64, (i64, i64, i64))> = Vec::new();
+    // steps during which the host we run on was (nearly) saturated, with its cpu utilization
+    let mut client_cpu: Vec<(u32, f64)> = Vec::new();
+    let workload = args.as_workload()?;
+    let statements = workload.statements_per_transaction();
+    // serialization failures roll back as well (see --isolation)
+    let isolation = workload.isolation;
+    // requested and actually connected clients per step
+    let mut clients: Vec<(u32, u32)> = Vec::new();
+
+    let tags = args.as_tags()?;
+    if !tags.is_empty() {
+        let tags: Vec<String> = tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        println!("Tags: {}", tags.join(", "));
+    }
+    if args.notify {
+        println!("Notify workload: TPS and Latency are notifications received per second and delivery latency.");
+    }
+    let table = report::ResultsTable::new(args.as_output_format()?, rtt.is_some());
+    let objective = args.as_objective()?;
+    for line in table.header() {
+        println!("{}", line);
+    }
+
+    let steps = args.as_steps()?;
+    let mut progress = prometheus::Progress {
+        steps_total: steps.len(),
+        steps_completed: 0,
+        phase: prometheus::Phase::Idle,
+    };
+    // how long every completed step took, for the estimate of --progress
+    let mut step_durations: Vec<std::time::Duration> = Vec::new();
+    for num_threads in steps.iter().copied() {
+        let step_start = std::time::Instant::now();
+        set_phase(args, &mut progress, prometheus::Phase::Ramp)?;
+        event(
+            args,
+            "step_start",
+            serde_json::json!({"clients": num_threads}),
+        );
+        threader.scaleup(num_threads)?;
+        if args.as_max_refused() < 100.0 {
+            let (refused, new) = threader.refused_connections(connect_timeout);
+            if new > 0 && 100.0 * refused as f64 / new as f64 > args.as_max_refused() {
+                refused_at = Some((num_threads, refused, new));
+                break;
+            }
+        }
+        if args.pre_connect {
+            connect_times.push((num_threads, threader.wait_connected(max_wait)));
         }
-        threader.scaleup(num_threads);
-        match threader.wait_stable(args.spread, args.min_samples as usize, max_wait) {
+        let phase = match (burst, transactions_per_step) {
+            (None, None) => prometheus::Phase::Warmup,
+            _ => prometheus::Phase::Measure,
+        };
+        set_phase(args, &mut progress, phase)?;
+        let cpu_before = probe::host_cpu_times();
+        let step_result = match (burst, transactions_per_step) {
+            (Some((burst_on, _)), _) => {
+                // so that the postgres stats only cover the burst
+                sampler.next()?;
+                threader.measure(burst_on, args.spread)?
+            }
+            (None, Some(transactions)) => {
+                sampler.next()?;
+                threader.run_transactions(transactions)?
+            }
+            (None, None) => threader.wait_stable(
+                args.spread,
+                args.min_samples as usize,
+                args.as_window(),
+                max_wait,
+            )?,
+        };
+        let utilization = probe::host_cpu_times()
+            .zip(cpu_before)
+            .and_then(|(after, before)| after.utilization_since(&before));
+        if let Some(utilization) = utilization.filter(|u| *u >= CLIENT_CPU_WARNING) {
+            client_cpu.push((num_threads, utilization));
+        }
+        if args.find_limit {
+            let totals = threader.step_totals();
+            let (transactions, errors) = (totals.transactions, totals.errors);
+            if errors > 0
+                && 100.0 * errors as f64 / (transactions + errors) as f64 > args.as_max_errors()
+            {
+                errors_at = Some((num_threads, errors, transactions + errors));
+                break;
+            }
+        }
+        match step_result {
             Some(result) => {
                 sampler.next()?;
+                clients.push((num_threads, threader.connected()));
+                if args.wait_events {
+                    wait_events.push((num_threads, sampler.wait_events()?));
+                }
+                if !max_idle_in_transaction.is_zero() {
+                    let (idle, longest) =
+                        sampler.idle_in_transaction(&workload.application_name())?;
+                    if longest > max_idle_in_transaction {
+                        idle_in_transaction.push((num_threads, idle, longest));
+                    }
+                }
+                if let Some(rates) = sampler.io_rates() {
+                    io.push((num_threads, rates));
+                }
                 let latency = result.latency.num_microseconds().unwrap() as f64;
+                let overhead = result.overhead.num_microseconds().unwrap() as f64;
                 let pg_tps: f64 = sampler.tps() as f64;
+                // no transactions means we cannot tell, a standby has no wal rate and a wal discontinuity is a reset
+                let (wal_per_sec, wal_per_xact) = match sampler.wal_per_sec() {
+                    Some(wal_per_sec) if pg_tps > 0.0 => (
+                        format!("{}", wal_per_sec as i32),
+                        format!("{:.1}", wal_per_sec as f64 / pg_tps),
+                    ),
+                    Some(wal_per_sec) => (format!("{}", wal_per_sec as i32), "?".to_string()),
+                    None if sampler.in_recovery => ("n/a".to_string(), "n/a".to_string()),
+                    None => ("reset".to_string(), "reset".to_string()),
+                };
                 if !result.stable {
                     instable = true;
                 }
-                println!(
-                    "| {0} | {1:7.5} | {2} {3:>11.3} | {4:>9.1} | {5:>11.3} | {6:>9.3} | {7:>9.3} |",
-                    chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                    num_threads,
+                if let (Some(_), Some((start, end))) = (transactions_per_step, result.window) {
+                    completed.push((num_threads, end - start));
+                }
+                if args.batch_stats {
+                    batches.push((num_threads, threader.step_batches()));
+                }
+                if workload.insert {
+                    let size = sampler.append_size(threader::INSERT_TABLE_NAME)?;
+                    inserts.push((num_threads, result.tps, size));
+                }
+                if args.count_rows {
+                    rows.push((num_threads, result.tps, threader.step_totals()));
+                }
+                if args.cold_latency {
+                    cold.push((num_threads, threader.step_totals()));
+                }
+                if args.shuffle {
+                    let totals = threader.step_totals();
+                    deadlocks.push((num_threads, totals.transactions, totals.deadlocks));
+                }
+                if args.rollback_ratio > 0.0 || isolation != Isolation::ReadCommitted {
+                    rollbacks.push((num_threads, result.rollback_ratio, wal_per_xact.clone()));
+                }
+                let mut cells = vec![
+                    chrono::offset::Local::now()
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string(),
+                    num_threads.to_string(),
                     match result.stable {
-                        true => " ",
-                        _ => "*",
-                    },
-                    result.tps,
-                    latency,
-                    result.tps / latency,
-                    pg_tps,
-                    sampler.wal_per_sec() as i32,
+                        true => "yes",
+                        _ => "no",
+                    }
+                    .to_string(),
+                    result.samples.to_string(),
+                    format!("{:.3}", result.tps),
+                    format!("{:.1}", latency),
+                    format!("{:.1}", overhead),
+                    format!("{:.3}", result.tps / latency),
+                    format!("{:.3}", pg_tps),
+                    wal_per_sec.clone(),
+                    wal_per_xact,
+                ];
+                if let Some(rtt) = rtt {
+                    // the latency without the round trip time, which is never negative
+                    cells.push(format!(
+                        "{:.1}",
+                        (latency - rtt.num_microseconds().unwrap() as f64).max(0.0)
+                    ));
+                }
+                println!("{}", table.row(&cells));
+                event(
+                    args,
+                    "step_result",
+                    serde_json::json!({
+                        "clients": num_threads,
+                        "stable": result.stable,
+                        "samples": result.samples,
+                        "tps": result.tps,
+                        "latency_usec": latency,
+                        "confidence": result.confidence,
+                    }),
+                );
+                if let Some(writer) = output.as_mut() {
+                    writer.write(num_threads, &result)?;
+                }
+                results.push((num_threads, result));
+                progress.steps_completed += 1;
+                set_phase(args, &mut progress, prometheus::Phase::Idle)?;
+                if let Some((_, burst_off)) = burst {
+                    threader.pause();
+                    std::thread::sleep(burst_off.to_std().unwrap_or_default());
+                    threader.resume();
+                }
+                if workload.wal_stress {
+                    // no new wal is written while the replicas replay the wal of the step
+                    threader.pause();
+                    catch_ups.push((num_threads, wal_per_sec, sampler.catch_up(max_wait)?));
+                    threader.resume();
+                }
+                step_durations.push(step_start.elapsed());
+                if args.progress {
+                    let eta = progress::eta(&steps, &step_durations, args.progress_weighted);
+                    eprintln!(
+                        "Progress: {}",
+                        progress::bar(step_durations.len(), steps.len(), eta)
                     );
+                }
+                // a limit is about errors, so a low TPS is no reason to stop looking for it
+                if !args.find_limit && args.min_tps > 0.0 && result.tps < args.min_tps {
+                    below_min_tps = Some((num_threads, result.tps));
+                    break;
+                }
             }
             None => {
-                println!(
-                    "| {0} | {1:7.5} |   {2:>11.3} | {3:>9.1} | {4:>11.3} | {5:>9.3} | {6:>9.3} |",
-                    chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                    num_threads,
-                    "?",
-                    "?",
-                    "?",
-                    "?",
-                    "?"
+                let mut cells = vec![
+                    chrono::offset::Local::now()
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string(),
+                    num_threads.to_string(),
+                ];
+                let columns = match rtt {
+                    Some(_) => 12,
+                    None => 11,
+                };
+                cells.resize(columns, "?".to_string());
+                println!("{}", table.row(&cells));
+                event(
+                    args,
+                    "step_result",
+                    serde_json::json!({
+                        "clients": num_threads,
+                        "stable": null,
+                        "samples": null,
+                        "tps": null,
+                        "latency_usec": null,
+                        "confidence": null,
+                    }),
                 );
                 break;
             }
         }
     }
-    println!("|---------------------|---------|---------------|-----------|-------------|-----------|-----------|");
+    set_phase(args, &mut progress, prometheus::Phase::Idle)?;
+    if let Some(footer) = table.footer() {
+        println!("{}", footer);
+    }
+    let metrics: Vec<cost::Metrics> = results
+        .iter()
+        .map(|(num_threads, result)| cost::Metrics {
+            clients: *num_threads,
+            tps: result.tps,
+            latency_usec: result.latency.num_microseconds().unwrap_or(0) as f64,
+            overhead_usec: result.overhead.num_microseconds().unwrap_or(0) as f64,
+        })
+        .collect();
+    if results
+        .iter()
+        .any(|(_, result)| result.confidence.is_some())
+    {
+        println!("Confidence in the result of every step (0-100%, from its spread, samples and if it stabilized):");
+        for (num_threads, result) in results.iter() {
+            if let Some(confidence) = result.confidence {
+                println!("- {:>7} clients: {:>5.1}%", num_threads, confidence);
+            }
+        }
+    }
+    if let Some((num_threads, score)) = objective.optimum(&metrics) {
+        let confidence = results
+            .iter()
+            .find(|(clients, _)| *clients == num_threads)
+            .and_then(|(_, result)| result.confidence);
+        match confidence {
+            Some(confidence) => println!(
+                "Optimum for {}: {} clients (scores {:.3}, {:.0}% confident)",
+                objective.name(),
+                num_threads,
+                score,
+                confidence
+            ),
+            None => println!(
+                "Optimum for {}: {} clients (scores {:.3})",
+                objective.name(),
+                num_threads,
+                score
+            ),
+        }
+    }
 
+    if let Some(transactions) = transactions_per_step {
+        println!(
+            "Time to complete {} transactions (--transactions-per-step):",
+            transactions
+        );
+        for (num_threads, duration) in completed.iter() {
+            println!(
+                "- {:>7} clients: {:>9.3}s",
+                num_threads,
+                duration.num_milliseconds() as f64 / 1000.0
+            );
+        }
+    }
     if instable {
-        println!("* Samples marked with '*' did not stabilize before max-wait.")
+        println!("Steps with Stable 'no' did not stabilize before max-wait.");
+        println!(
+            "Their results are the mean of the Samples (timeslices of 200ms) collected until then."
+        )
+    }
+    if let Some((num_threads, refused, new)) = refused_at {
+        println!(
+            "Stopped: the server refused connections at {} clients ({} of {} new clients did not connect within {}s)",
+            num_threads,
+            refused,
+            new,
+            connect_timeout.num_seconds()
+        );
+        match results.last() {
+            Some((last_good, _)) => println!("The last good step was {} clients", last_good),
+            None => println!("There was no good step"),
+        }
+    }
+    if let Some((num_threads, errors, attempts)) = errors_at {
+        println!(
+            "Stopped: queries failed at {} clients ({} of {} transactions failed, more than --max-errors {}%)",
+            num_threads, errors, attempts, args.as_max_errors()
+        );
+    }
+    if args.find_limit {
+        let last_clean = results
+            .last()
+            .map(|(num_threads, _)| format!("{} clients", num_threads))
+            .unwrap_or_else(|| "no step".to_string());
+        match (refused_at, errors_at) {
+            (Some(_), _) => println!(
+                "Limit: connections refused, the last clean step was {}",
+                last_clean
+            ),
+            (_, Some(_)) => println!(
+                "Limit: query errors, the last clean step was {}",
+                last_clean
+            ),
+            _ => println!(
+                "Limit: not found, {} ran cleanly (raise --range to look further)",
+                last_clean
+            ),
+        }
+    }
+    if let Some((num_threads, tps)) = below_min_tps {
+        println!(
+            "Stopped at {} clients: {:.3} TPS is below --min-tps {}",
+            num_threads, tps, args.min_tps
+        );
+    }
+    if !client_cpu.is_empty() {
+        event(
+            args,
+            "warning",
+            serde_json::json!({"msg": "this host was (nearly) out of cpu, so the clients may be the bottleneck"}),
+        );
+        println!("Warning: this host was (nearly) out of cpu, so the clients may be the bottleneck and the results understate what the server can do");
+        println!("(unless postgres runs on this host too). Consider running pg_tps_optimizer from a bigger host, or from more hosts at once:");
+        for (num_threads, utilization) in client_cpu {
+            println!(
+                "- {:>7} clients: {:>6.1}% cpu on this host",
+                num_threads, utilization
+            );
+        }
+    }
+    if clients
+        .iter()
+        .any(|(requested, connected)| connected < requested)
+    {
+        event(
+            args,
+            "warning",
+            serde_json::json!({"msg": "not all clients were connected"}),
+        );
+        println!("Warning: not all clients were connected, so Clients overstates some steps:");
+        for (requested, connected) in clients {
+            println!(
+                "- {:>7} clients requested: {:>7} connected",
+                requested, connected
+            );
+        }
+    }
+    if let Some((_, _, longest)) = idle_in_transaction
+        .iter()
+        .max_by_key(|(_, _, longest)| *longest)
+    {
+        let msg = format!(
+            "clients held transactions open without running a statement for up to {:.3}s (more than --max-idle-in-transaction {})",
+            longest.num_milliseconds() as f64 / 1000.0,
+            args.max_idle_in_transaction
+        );
+        event(args, "warning", serde_json::json!({ "msg": msg }));
+        println!("Warning: {}, which holds back vacuum:", msg);
+        for (num_threads, idle, longest) in idle_in_transaction {
+            println!(
+                "- {:>7} clients: {:>7} idle in transaction, the longest for {:.3}s",
+                num_threads,
+                idle,
+                longest.num_milliseconds() as f64 / 1000.0
+            );
+        }
+    }
+    if statements > 1 {
+        print_batch_commit(&results, statements, args.count_statements);
+    }
+    if !rollbacks.is_empty() {
+        println!(
+            "Achieved rollback ratio (--rollback-ratio {}%, --isolation {}) and the wal it resulted in:",
+            args.rollback_ratio,
+            isolation.as_str()
+        );
+        for (num_threads, ratio, wal_per_xact) in rollbacks {
+            println!(
+                "- {:>7} clients: {:>6.2}% rolled back, {:>9} wal/xact bytes",
+                num_threads,
+                100.0 * ratio,
+                wal_per_xact
+            );
+        }
+    }
+    if !batches.is_empty() {
+        println!("Batch size per timeslice (on average per sample), where a sample should take about 100 msec:");
+        for (num_threads, timeslices) in batches {
+            println!("- {:>7} clients:", num_threads);
+            for (timeslice, requested, achieved, duration) in timeslices {
+                println!(
+                    "  - timeslice {}: {:>9.1} requested, {:>9.1} achieved, {:>7.1} msec",
+                    timeslice, requested, achieved, duration
+                );
+            }
+        }
+    }
+    if !rows.is_empty() {
+        println!("Rows affected (--count-rows), where (close to) 0 means the query does not write what it should:");
+        for (num_threads, tps, totals) in rows {
+            let per_transaction = totals.rows_per_transaction();
+            println!(
+                "- {:>7} clients: {:>12.3} rows/s, {:>9.3} rows/xact",
+                num_threads,
+                tps * per_transaction,
+                per_transaction
+            );
+        }
+    }
+    if !cold.is_empty() {
+        println!(
+            "Latency of the first transaction of every session (cold) and of all others (warm):"
+        );
+        for (num_threads, totals) in cold {
+            println!(
+                "- {:>7} clients: {:>9} cold of {:>9} transactions, cold {:>9} usec, warm {:>9} usec",
+                num_threads,
+                totals.cold,
+                totals.transactions,
+                totals.cold_latency().num_microseconds().unwrap_or_default(),
+                totals.warm_latency().num_microseconds().unwrap_or_default()
+            );
+        }
+    }
+    if !deadlocks.is_empty() {
+        println!("Deadlocks (--shuffle), which were rolled back:");
+        for (num_threads, transactions, deadlocked) in deadlocks {
+            println!(
+                "- {:>7} clients: {:>9} of {:>9} transactions",
+                num_threads, deadlocked, transactions
+            );
+        }
+    }
+    if !wait_events.is_empty() {
+        println!("Active backends per wait event type (snapshot at the end of every step):");
+        for (num_threads, events) in wait_events {
+            let fractions: Vec<String> = events
+                .fractions()
+                .iter()
+                .map(|(w_type, fraction)| format!("{} {:.0}%", w_type, 100.0 * fraction))
+                .collect();
+            println!(
+                "- {:>7} clients: dominant {}, {}",
+                num_threads,
+                events.dominant().unwrap_or_else(|| "?".to_string()),
+                fractions.join(", ")
+            );
+        }
+    }
+    if !io.is_empty() {
+        println!("Io operations per second by context (from pg_stat_io, between the end of the previous and of every step):");
+        for (num_threads, rates) in io {
+            let contexts: Vec<String> = rates
+                .iter()
+                .map(|rates| {
+                    format!(
+                        "{} {:.1} reads, {:.1} writes, {:.1} extends",
+                        rates.context, rates.reads, rates.writes, rates.extends
+                    )
+                })
+                .collect();
+            println!(
+                "- {:>7} clients: {}",
+                num_threads,
+                match contexts.is_empty() {
+                    true => "no io".to_string(),
+                    false => contexts.join("; "),
+                }
+            );
+        }
+    }
+    if !catch_ups.is_empty() {
+        println!("Wal generated and time for the replicas to replay it (--wal-stress):");
+        for (num_threads, wal_per_sec, catch_up) in catch_ups {
+            let replicas = match catch_up {
+                CatchUp::NoReplicas => "no replicas".to_string(),
+                CatchUp::Replayed(duration) => {
+                    format!("replicas caught up in {} msec", duration.num_milliseconds())
+                }
+                CatchUp::TimedOut => "replicas did not catch up before max-wait".to_string(),
+            };
+            println!(
+                "- {:>7} clients: {:>9} wal bytes/s, {}",
+                num_threads, wal_per_sec, replicas
+            );
+        }
+    }
+    if !inserts.is_empty() {
+        println!(
+            "Rows inserted (--insert), and the size of the table and its index after every step:"
+        );
+        for (num_threads, tps, (table, index, inserted)) in inserts {
+            let per_row = match inserted {
+                0 => "?".to_string(),
+                _ => format!("{:.1}", (table + index) as f64 / inserted as f64),
+            };
+            println!(
+                "- {:>7} clients: {:>12.3} rows/s, {:>11} rows, table {:>9} kB, index {:>9} kB, {:>7} bytes/row",
+                num_threads,
+                tps,
+                inserted,
+                table / 1024,
+                index / 1024,
+                per_row
+            );
+        }
+    }
+    if !connect_times.is_empty() {
+        println!("Time to connect and initialize all clients:");
+        for (num_threads, connect_time) in connect_times {
+            match connect_time {
+                Some(duration) => println!(
+                    "- {:>7} clients: {:>9} msec",
+                    num_threads,
+                    duration.num_milliseconds()
+                ),
+                None => println!(
+                    "- {:>7} clients: not all clients connected before max-wait",
+                    num_threads
+                ),
+            }
+        }
+    }
+    Ok(results)
+}
+
+// Wait for the result of the current number of clients of threader
+fn stable_result(
+    args: &Params,
+    threader: &mut Threader,
+    max_wait: chrono::Duration,
+) -> Result<Option<TestResult>, PgTpsError> {
+    if args.pre_connect {
+        threader.wait_connected(max_wait);
+    }
+    threader.wait_stable(
+        args.spread,
+        args.min_samples as usize,
+        args.as_window(),
+        max_wait,
+    )
+}
+
+// What a search looks for (see --target-latency and --target-cpu-utilization)
+enum Target {
+    // the latency (usec) of a number of clients
+    Latency(f64),
+    // the CPU utilization (percent) of the server with a number of clients, and the command that probes it
+    Cpu(f64, String),
+}
+
+// The state of a search for the highest number of clients within target
+struct TargetSearch {
+    target: Target,
+    results: Vec<(u32, TestResult)>,
+    // the highest number of clients within target (with its result and CPU utilization), and the lowest over it
+    within: Option<(u32, TestResult, Option<f64>)>,
+    over: Option<u32>,
+    table: report::SearchTable,
+}
+
+impl TargetSearch {
+    // Print and record (also to output) the result of a number of clients, and return if it is within target.
+    // The CPU utilization is probed right away, while the clients are still running.
+    fn probe(
+        &mut self,
+        num_threads: u32,
+        result: Option<TestResult>,
+        output: &mut Option<msgpack::Writer>,
+    ) -> Result<bool, PgTpsError> {
+        let mut cells = vec![
+            chrono::offset::Local::now()
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            num_threads.to_string(),
+        ];
+        let result = match result {
+            Some(result) => result,
+            None => {
+                cells.extend(["?", "?", "?"].map(String::from));
+                if let Target::Cpu(_, _) = self.target {
+                    cells.push("?".to_string());
+                }
+                cells.push("no".to_string());
+                println!("{}", self.table.row(&cells));
+                self.over = Some(num_threads);
+                return Ok(false);
+            }
+        };
+        let latency = result.latency.num_microseconds().unwrap() as f64;
+        let (within, cpu) = match &self.target {
+            Target::Latency(target_usec) => (latency <= *target_usec, None),
+            Target::Cpu(target, command) => {
+                let cpu = probe::cpu_utilization(command)?;
+                (cpu <= *target, Some(cpu))
+            }
+        };
+        cells.extend([
+            match result.stable {
+                true => "yes",
+                _ => "no",
+            }
+            .to_string(),
+            format!("{:.3}", result.tps),
+            format!("{:.1}", latency),
+        ]);
+        if let Some(cpu) = cpu {
+            cells.push(format!("{:.1}", cpu));
+        }
+        cells.push(
+            match within {
+                true => "yes",
+                _ => "no",
+            }
+            .to_string(),
+        );
+        println!("{}", self.table.row(&cells));
+        if let Some(writer) = output.as_mut() {
+            writer.write(num_threads, &result)?;
+        }
+        self.results.push((num_threads, result));
+        match within {
+            true => self.within = Some((num_threads, result, cpu)),
+            false => self.over = Some(num_threads),
+        }
+        Ok(within)
+    }
+    // The next number of clients to bisect with, or None when within and over are 1 client apart
+    fn next(&self) -> Option<u32> {
+        match (self.within, self.over) {
+            (Some((low, _, _)), Some(high)) if high - low > 1 => Some(low + (high - low) / 2),
+            _ => None,
+        }
+    }
+}
+
+// Search the highest number of clients within target (see --target-latency and --target-cpu-utilization).
+// First ramp up through the steps until a step exceeds target (or has no result),
+// then bisect between the last step within target and the first step over it until they are 1 client apart.
+// Clients can only be added, so every probe of the bisection runs with a fresh set of clients.
+fn target_search(
+    args: &Params,
+    w: &Workload,
+    target: Target,
+    max_threads: u32,
+    output: &mut Option<msgpack::Writer>,
+) -> Result<Vec<(u32, TestResult)>, PgTpsError> {
+    let max_wait = args.as_max_wait()?;
+    match &target {
+        Target::Latency(target_usec) => println!(
+            "Searching the highest number of clients with a latency of at most {}usec",
+            target_usec
+        ),
+        Target::Cpu(target, command) => println!(
+            "Searching the highest number of clients with a CPU utilization of at most {}% (probed with {})",
+            target, command
+        ),
+    }
+    let table = report::SearchTable::new(
+        args.as_output_format()?,
+        matches!(target, Target::Cpu(_, _)),
+    );
+    for line in table.header() {
+        println!("{}", line);
+    }
+    let mut search = TargetSearch {
+        target,
+        results: Vec::new(),
+        within: None,
+        over: None,
+        table,
+    };
+    let mut threader = new_threader(args, w, max_threads)?;
+    for num_threads in args.as_steps()? {
+        threader.scaleup(num_threads)?;
+        if !search.probe(
+            num_threads,
+            stable_result(args, &mut threader, max_wait)?,
+            output,
+        )? {
+            break;
+        }
     }
-    println!("Stopping, but lets give the threads some time to stop");
     threader.finish();
+    while let Some(num_threads) = search.next() {
+        let mut threader = new_threader(args, w, num_threads)?;
+        threader.scaleup(num_threads)?;
+        let result = stable_result(args, &mut threader, max_wait)?;
+        search.probe(num_threads, result, output)?;
+        threader.finish();
+    }
+    if let Some(footer) = search.table.footer() {
+        println!("{}", footer);
+    }
 
-    println!("Finished");
-    ::std::process::exit(0);
+    match search.within {
+        Some((num_threads, result, cpu)) => println!(
+            "Highest number of clients within target: {} clients, {:.3} TPS at {}usec{}{}",
+            num_threads,
+            result.tps,
+            result.latency.num_microseconds().unwrap(),
+            cpu.map(|cpu| format!(" and {:.1}% CPU", cpu))
+                .unwrap_or_default(),
+            match search.over {
+                Some(_) => "",
+                None => " (every step was within target, raise --range to look further)",
+            }
+        ),
+        None => println!("No number of clients is within target, not even the first step"),
+    }
+    search.results.sort_by_key(|(num_threads, _)| *num_threads);
+    Ok(search.results)
+}
+
+// Run a sql script (see --setup-file and --teardown-file) on a connection of its own, bypassing a pooler
+fn run_script(args: &Params, path: &str) -> Result<(), PgTpsError> {
+    let script = std::fs::read_to_string(shellexpand::tilde(path).to_string())?;
+    args.as_direct_dsn()?.client()?.batch_execute(&script)?;
+    Ok(())
+}
+
+// Sample the server every monitor_interval until interrupted, without running any clients (see --monitor).
+// This only takes one connection that runs one query per interval.
+fn monitor(args: &Params) -> Result<(), PgTpsError> {
+    if args.dsns.len() > 1 {
+        return Err(PgTpsError::Config(
+            "monitor cannot be combined with multiple dsns".to_string(),
+        ));
+    }
+    let interval = args.as_monitor_interval()?.to_std().unwrap_or_default();
+    let table = report::MonitorTable::new(args.as_output_format()?);
+    let mut sampler = PgSampler::new(args.as_direct_dsn()?)?;
+    if sampler.in_recovery {
+        println!("Connected to a standby (recovery is in progress), so wal metrics are n/a");
+    }
+    for line in table.header() {
+        println!("{}", line);
+    }
+    loop {
+        std::thread::sleep(interval);
+        sampler.next()?;
+        let wal_per_sec = match sampler.wal_per_sec() {
+            Some(wal_per_sec) => format!("{:.1}", wal_per_sec / 1024.0),
+            None if sampler.in_recovery => "n/a".to_string(),
+            None => "reset".to_string(),
+        };
+        println!(
+            "{}",
+            table.row(&[
+                chrono::offset::Local::now()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                format!("{:.3}", sampler.tps()),
+                wal_per_sec,
+                sampler
+                    .cache_hit_ratio()
+                    .map(|ratio| format!("{:.2}", ratio))
+                    .unwrap_or_else(|| "?".to_string()),
+            ])
+        );
+    }
+}
+
+// Print what the server supports as json, so that a script can decide which flags to pass (see --probe)
+fn capabilities(args: &Params) -> Result<(), PgTpsError> {
+    if args.dsns.len() > 1 {
+        return Err(PgTpsError::Config(
+            "probe cannot be combined with multiple dsns".to_string(),
+        ));
+    }
+    let capabilities = PgSampler::new(args.as_direct_dsn()?)?.capabilities()?;
+    let features: serde_json::Map<String, serde_json::Value> = capabilities
+        .features()
+        .into_iter()
+        .map(|(name, supported)| (name.to_string(), supported.into()))
+        .collect();
+    println!(
+        "{:#}",
+        serde_json::json!({
+            "server_version": capabilities.version,
+            "server_version_num": capabilities.version_num,
+            "in_recovery": capabilities.in_recovery,
+            "max_connections": capabilities.max_connections,
+            "extensions": capabilities.extensions,
+            "features": features,
+        })
+    );
+    Ok(())
+}
+
+// Move the sweep to the next phase, and with --textfile expose that (and how far along the sweep is) right away
+fn set_phase(
+    args: &Params,
+    progress: &mut prometheus::Progress,
+    phase: prometheus::Phase,
+) -> Result<(), PgTpsError> {
+    progress.phase = phase;
+    if !args.textfile.is_empty() {
+        let text = prometheus::terminate(prometheus::progress(progress), args.openmetrics);
+        prometheus::write_textfile(&args.textfile, &text)?;
+    }
+    Ok(())
+}
+
+// With --batch-commit a transaction runs multiple statements, so print per transaction and per statement
+fn print_batch_commit(results: &[(u32, TestResult)], statements: u32, count_statements: bool) {
+    let statements = statements as f64;
+    println!(
+        "Every transaction ran {} statements (--batch-commit), and the table counts every {}:",
+        statements,
+        match count_statements {
+            true => "statement",
+            false => "transaction",
+        }
+    );
+    for (num_threads, result) in results {
+        let latency = result.latency.num_microseconds().unwrap_or(0) as f64;
+        // convert the table's interpretation to per transaction
+        let (tps, latency) = match count_statements {
+            true => (result.tps / statements, latency * statements),
+            false => (result.tps, latency),
+        };
+        println!(
+            "- {:>7} clients: {:>13.3} transactions/s ({:>9.1} usec), {:>13.3} statements/s ({:>9.1} usec)",
+            num_threads,
+            tps,
+            latency,
+            tps * statements,
+            latency / statements
+        );
+    }
+}
+
+fn print_aggregate(aggregate: &Aggregate, format: report::OutputFormat) {
+    println!("Summary over all runs:");
+    let table = report::AggregateTable::new(format);
+    for line in table.header() {
+        println!("{}", line);
+    }
+    for (num_threads, tps, latency) in aggregate.summaries() {
+        let cells = vec![
+            num_threads.to_string(),
+            tps.runs.to_string(),
+            format!("{:.3}", tps.median),
+            format!("{:.3}", tps.mean),
+            format!("{:.3}", tps.stddev),
+            format!("{:.2}", tps.cv),
+            format!("{:.1}", latency.median),
+            format!("{:.1}", latency.mean),
+            format!("{:.1}", latency.stddev),
+            format!("{:.2}", latency.cv),
+        ];
+        println!("{}", table.row(&cells));
+    }
+    if let Some(footer) = table.footer() {
+        println!("{}", footer);
+    }
+    println!("CV % is the standard deviation as percentage of the mean. The lower, the more reproducible the results.");
 }
@@ -0,0 +1,97 @@
+/*
+Msgpack streams the result of every step to a file while the run is going (see --msgpack).
+Every record is a 4 byte (big endian) length, followed by that many bytes of MessagePack,
+which is far more compact than text for runs with many steps.
+*/
+use crate::error::PgTpsError;
+use crate::json::SCHEMA_VERSION;
+use crate::threader::TestResult;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub struct Writer {
+    file: BufWriter<File>,
+    // the labels of the current target (e.g. host and tags), added to every record
+    pub labels: BTreeMap<String, String>,
+}
+
+// One step as a record, with the same schema_version as the json document
+pub fn record(labels: &BTreeMap<String, String>, clients: u32, result: &TestResult) -> Value {
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "time": chrono::Utc::now().to_rfc3339(),
+        "labels": labels,
+        "clients": clients,
+        "stable": result.stable,
+        "samples": result.samples,
+        "tps": result.tps,
+        "latency_usec": result.latency.num_microseconds().unwrap_or_default(),
+        "overhead_usec": result.overhead.num_microseconds().unwrap_or_default(),
+        "rollback_ratio": result.rollback_ratio,
+        "window_start": result.window.map(|(start, _)| start.to_rfc3339()),
+        "window_end": result.window.map(|(_, end)| end.to_rfc3339()),
+    })
+}
+
+// A record with its length prefix
+fn encode(record: &Value) -> Vec<u8> {
+    let body = rmp_serde::to_vec_named(record).expect("a json value always encodes");
+    let mut bytes = (body.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(body);
+    bytes
+}
+
+impl Writer {
+    pub fn create(path: &str) -> Result<Writer, PgTpsError> {
+        Ok(Writer {
+            file: BufWriter::new(File::create(path)?),
+            labels: BTreeMap::new(),
+        })
+    }
+    // Flushed right away, so the file is complete up to the last step when a run is interrupted
+    pub fn write(&mut self, clients: u32, result: &TestResult) -> Result<(), PgTpsError> {
+        self.file
+            .write_all(&encode(&record(&self.labels, clients, result)))?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_encode() {
+        let labels = BTreeMap::from([("host".to_string(), "db1".to_string())]);
+        let result = TestResult {
+            stable: true,
+            tps: 1500.0,
+            latency: Duration::microseconds(2500),
+            overhead: Duration::zero(),
+            rollback_ratio: 0.0,
+            samples: 10,
+            stddev: None,
+            window: Some((
+                Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+                Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 15).unwrap(),
+            )),
+            trace: None,
+            confidence: None,
+        };
+        let bytes = encode(&record(&labels, 8, &result));
+        let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        assert_eq!(length, bytes.len() - 4);
+        let decoded: Value = rmp_serde::from_slice(&bytes[4..]).unwrap();
+        assert_eq!(decoded["schema_version"], SCHEMA_VERSION);
+        assert_eq!(decoded["labels"]["host"], "db1");
+        assert_eq!(decoded["clients"], 8);
+        assert_eq!(decoded["tps"], 1500.0);
+        assert_eq!(decoded["latency_usec"], 2500);
+        assert_eq!(decoded["window_start"], "2026-01-02T03:04:05+00:00");
+        assert_eq!(decoded["window_end"], "2026-01-02T03:04:15+00:00");
+    }
+}
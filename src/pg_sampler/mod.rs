@@ -3,16 +3,259 @@ Pg_sampler can be used to periodically get statistics information from PostgreSQ
 The main idea is to get the number of transactions and de amount of WAL.
 We also capture the duration between 2 samples, and as such also know TPS and WAL per sec.
 */
+use crate::aggregate::Summary;
 use crate::dsn::Dsn;
+use crate::error::PgTpsError;
 use chrono::Utc;
-use postgres::{Client, Error, Statement};
+use postgres::{Client, Statement};
 
 const SAMPLE_QUERY: &str = "
 SELECT now()::timestamp as samplemmoment,
 pg_current_wal_lsn()::varchar as lsn,
 (pg_current_wal_lsn() - $1::varchar::pg_lsn)::real as walbytes,
 (select sum(xact_commit+xact_rollback)::real
- FROM pg_stat_database) as transacts";
+ FROM pg_stat_database) as transacts,
+(select sum(blks_hit)::float8 FROM pg_stat_database) as blks_hit,
+(select sum(blks_read)::float8 FROM pg_stat_database) as blks_read";
+
+// A standby cannot tell its current wal position, so on a standby we skip wal metrics
+const RECOVERY_SAMPLE_QUERY: &str = "
+SELECT now()::timestamp as samplemmoment,
+$1::varchar as lsn,
+0::real as walbytes,
+(select sum(xact_commit+xact_rollback)::real
+ FROM pg_stat_database) as transacts,
+(select sum(blks_hit)::float8 FROM pg_stat_database) as blks_hit,
+(select sum(blks_read)::float8 FROM pg_stat_database) as blks_read";
+
+// The number of replicas, and whether all of them replayed the wal up to $1
+const REPLAYED_QUERY: &str = "
+SELECT count(*)::int, coalesce(bool_and(replay_lsn >= $1::varchar::pg_lsn), true)
+FROM pg_stat_replication";
+
+const STATEMENTS_EXTENSION_QUERY: &str = "
+SELECT count(*) > 0 FROM pg_extension WHERE extname = 'pg_stat_statements'";
+
+// pg_stat_io exists as of PostgreSQL 16, and pg_stat_statements only works when installed in the database we connect to
+const CAPABILITIES_QUERY: &str = "
+SELECT current_setting('server_version'), current_setting('server_version_num')::int,
+current_setting('max_connections')::int, to_regclass('pg_catalog.pg_stat_io') IS NOT NULL,
+coalesce((SELECT array_agg(extname::text ORDER BY extname) FROM pg_extension), '{}')";
+
+// The settings that shape the results most, printed so that a saved run documents what it ran against
+pub const SETTINGS: [&str; 7] = [
+    "shared_buffers",
+    "max_connections",
+    "work_mem",
+    "synchronous_commit",
+    "max_wal_size",
+    "wal_compression",
+    "effective_cache_size",
+];
+
+const SETTINGS_QUERY: &str = "
+SELECT name, current_setting(name)
+FROM pg_settings
+WHERE name = ANY($1)";
+
+// Backends that are not waiting are running on (or waiting for) CPU
+const WAIT_EVENTS_QUERY: &str = "
+SELECT coalesce(wait_event_type, 'CPU'), coalesce(wait_event, 'CPU'), count(*)
+FROM pg_stat_activity
+WHERE state = 'active' AND pid <> pg_backend_pid()
+GROUP BY 1, 2";
+
+// Before PostgreSQL 9.6 we can only tell if a backend is waiting on a lock
+const WAITING_QUERY: &str = "
+SELECT CASE WHEN waiting THEN 'Lock' ELSE 'CPU' END,
+CASE WHEN waiting THEN 'Lock' ELSE 'CPU' END,
+count(*)
+FROM pg_stat_activity
+WHERE state = 'active' AND pid <> pg_backend_pid()
+GROUP BY 1, 2";
+
+// The io operations of all backend types per context, from pg_stat_io (PostgreSQL 16+),
+// where operations that do not apply to a backend type and context are null
+const IO_QUERY: &str = "
+SELECT now()::timestamp, context,
+coalesce(sum(reads), 0)::float8, coalesce(sum(writes), 0)::float8, coalesce(sum(extends), 0)::float8
+FROM pg_stat_io
+GROUP BY context
+ORDER BY context";
+
+// The number of backends of the clients (by application_name, or the trace id of --exemplars) that are idle in a transaction,
+// and how long the longest of them has been (in seconds)
+const IDLE_IN_TRANSACTION_QUERY: &str = "
+SELECT count(*)::int8, coalesce(extract(epoch FROM max(now() - state_change)), 0)::float8
+FROM pg_stat_activity
+WHERE state = 'idle in transaction' AND pid <> pg_backend_pid()
+AND (application_name = $1 OR application_name LIKE 'pg_tps_optimizer trace_id=%')";
+
+// total_time was renamed to total_exec_time in PostgreSQL 13
+const TOP_STATEMENTS_QUERY: &str = "
+SELECT regexp_replace(query, '\\s+', ' ', 'g') as query, calls, {total_time} as total_time
+FROM pg_stat_statements
+WHERE dbid = (SELECT oid FROM pg_database WHERE datname = current_database())
+AND userid = (SELECT oid FROM pg_roles WHERE rolname = current_user)
+ORDER BY 3 DESC
+LIMIT $1";
+
+// A summary of one line from pg_stat_statements
+pub struct StatementStats {
+    pub query: String,
+    pub calls: i64,
+    pub total_time: f64,
+}
+
+impl StatementStats {
+    // mean execution time in milliseconds
+    pub fn mean_time(&self) -> f64 {
+        match self.calls {
+            0 => 0.0,
+            calls => self.total_time / calls as f64,
+        }
+    }
+}
+
+// Estimates the network round trip time as the median duration of empty queries.
+// This uses a throwaway connection, so that it is not influenced by anything else.
+pub fn measure_rtt(dsn: Dsn, round_trips: u32) -> Result<chrono::Duration, PgTpsError> {
+    let mut client: Client = dsn.client()?;
+    // the first round trip also warms up the connection, so it is not counted
+    client.simple_query("SELECT")?;
+    let mut durations: Vec<f64> = Vec::new();
+    for _ in 0..round_trips {
+        let start = Utc::now();
+        client.simple_query("SELECT")?;
+        durations.push((Utc::now() - start).num_microseconds().unwrap_or_default() as f64);
+    }
+    let median = Summary::from_values(&durations).median;
+    Ok(chrono::Duration::microseconds(median as i64))
+}
+
+// WaitEvents is a snapshot of the number of active backends per wait event type and wait event
+pub struct WaitEvents {
+    events: Vec<(String, String, i64)>,
+}
+
+impl WaitEvents {
+    fn total(&self) -> i64 {
+        self.events.iter().map(|(_, _, count)| count).sum()
+    }
+    // The wait event (as type/event) most active backends are waiting on
+    pub fn dominant(&self) -> Option<String> {
+        self.events
+            .iter()
+            .max_by_key(|(_, _, count)| *count)
+            .map(|(w_type, event, _)| match w_type == event {
+                true => w_type.to_string(),
+                false => format!("{}/{}", w_type, event),
+            })
+    }
+    // The fraction of active backends per wait event type, largest first
+    pub fn fractions(&self) -> Vec<(String, f64)> {
+        let total = self.total();
+        if total == 0 {
+            return Vec::new();
+        }
+        let mut per_type: Vec<(String, i64)> = Vec::new();
+        for (w_type, _, count) in self.events.iter() {
+            match per_type.iter_mut().find(|(t, _)| t == w_type) {
+                Some((_, type_count)) => *type_count += count,
+                None => per_type.push((w_type.to_string(), *count)),
+            }
+        }
+        per_type.sort_by(|(_, a), (_, b)| b.cmp(a));
+        per_type
+            .into_iter()
+            .map(|(w_type, count)| (w_type, count as f64 / total as f64))
+            .collect()
+    }
+}
+
+// What a server supports, and therefore which features of the tool are usable against it (see --probe)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub version: String,
+    pub version_num: i32,
+    pub in_recovery: bool,
+    pub max_connections: i32,
+    // the extensions installed in the database we connect to
+    pub extensions: Vec<String>,
+    pub pg_stat_io: bool,
+}
+
+impl Capabilities {
+    // The features (named as their flags) that depend on the server, and whether this server supports them
+    pub fn features(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            // the built in workloads write to the benchmark tables, which a standby cannot
+            ("benchmark", !self.in_recovery),
+            // procedures exist as of PostgreSQL 11
+            ("call", !self.in_recovery && self.version_num >= 110000),
+            (
+                "pg_stat_statements",
+                self.extensions.iter().any(|e| e == "pg_stat_statements"),
+            ),
+            ("pg_stat_io", self.pg_stat_io),
+        ]
+    }
+}
+
+// The io operations per second of a context (e.g. normal, vacuum, bulkread or bulkwrite) between two readings of pg_stat_io
+#[derive(Clone, Debug, PartialEq)]
+pub struct IoRates {
+    pub context: String,
+    pub reads: f64,
+    pub writes: f64,
+    pub extends: f64,
+}
+
+// A reading of the cumulative reads, writes and extends per context in pg_stat_io (see --pg-stat-io)
+#[derive(Clone)]
+struct IoReading {
+    samplemoment: chrono::NaiveDateTime,
+    contexts: Vec<(String, f64, f64, f64)>,
+}
+
+impl IoReading {
+    // The rates of every context that did any io since previous, like the rates of TransactDataSample
+    fn rates_since(&self, previous: &IoReading) -> Vec<IoRates> {
+        let seconds = (self.samplemoment - previous.samplemoment)
+            .num_nanoseconds()
+            .unwrap() as f64
+            / 1.0e+9;
+        if seconds <= 0.0 {
+            return Vec::new();
+        }
+        self.contexts
+            .iter()
+            .map(|(context, reads, writes, extends)| {
+                let (before_reads, before_writes, before_extends) = previous
+                    .contexts
+                    .iter()
+                    .find(|(c, _, _, _)| c == context)
+                    .map(|(_, reads, writes, extends)| (*reads, *writes, *extends))
+                    .unwrap_or_default();
+                IoRates {
+                    context: context.to_string(),
+                    reads: (reads - before_reads) / seconds,
+                    writes: (writes - before_writes) / seconds,
+                    extends: (extends - before_extends) / seconds,
+                }
+            })
+            .filter(|rates| rates.reads > 0.0 || rates.writes > 0.0 || rates.extends > 0.0)
+            .collect()
+    }
+}
+
+// Whether (and how fast) the replicas replayed the wal of a step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CatchUp {
+    NoReplicas,
+    Replayed(chrono::Duration),
+    TimedOut,
+}
 
 // This struct can run a query against postgres and see
 pub struct PgSampler {
@@ -20,21 +263,46 @@ pub struct PgSampler {
     statement: Statement,
     previous: TransactDataSample,
     latest: TransactDataSample,
+    // connected to a standby (e.g. a read replica), which has no wal metrics
+    pub in_recovery: bool,
+    // a wal discontinuity (e.g. a failover) is only explained once
+    wal_reset_warned: bool,
+    // the previous and latest reading of pg_stat_io, when it is tracked (see track_io)
+    io: Option<(IoReading, IoReading)>,
 }
 
 impl PgSampler {
-    pub fn new(dsn: Dsn) -> Result<PgSampler, Error> {
-        let mut client: Client = dsn.client().unwrap();
-        let statement: Statement = client.prepare(SAMPLE_QUERY)?;
-        Ok(PgSampler {
+    pub fn new(dsn: Dsn) -> Result<PgSampler, PgTpsError> {
+        let mut client: Client = dsn.client()?;
+        let in_recovery: bool = client
+            .query_one("SELECT pg_is_in_recovery()", &[])
+            .map_err(PgTpsError::Sampler)?
+            .get(0);
+        let query = match in_recovery {
+            true => RECOVERY_SAMPLE_QUERY,
+            false => SAMPLE_QUERY,
+        };
+        let statement: Statement = client.prepare(query).map_err(PgTpsError::Sampler)?;
+        let mut sampler = PgSampler {
             client,
             statement,
+            in_recovery,
+            wal_reset_warned: false,
+            io: None,
             previous: TransactDataSample::new(),
             latest: TransactDataSample::new(),
-        })
+        };
+        // The first reading compares against 0/0 and zero transactions, which is no baseline at all.
+        // Taking it here means that after the next reading all rates are between two real readings.
+        sampler.next()?;
+        Ok(sampler)
     }
-    pub fn next(&mut self) -> Result<(), Error> {
-        let rows = self.client.query(&self.statement, &[&self.previous.lsn])?;
+    pub fn next(&mut self) -> Result<(), PgTpsError> {
+        // wal_bytes is the amount of wal since the latest reading, which becomes the previous reading
+        let rows = self
+            .client
+            .query(&self.statement, &[&self.latest.lsn])
+            .map_err(PgTpsError::Sampler)?;
         assert_eq!(rows.len(), 1);
         let row = rows.first().unwrap();
         self.previous = self.latest.clone();
@@ -43,24 +311,236 @@ impl PgSampler {
             lsn: row.get(1),
             wal_bytes: row.get(2),
             num_transactions: row.get(3),
+            blks_hit: row.get(4),
+            blks_read: row.get(5),
         };
+        if !self.in_recovery && self.latest.wal_bytes < 0.0 && !self.wal_reset_warned {
+            eprintln!("Warning: the wal position went backward (e.g. after a failover or a restore), so the wal rate of that step is shown as reset");
+            self.wal_reset_warned = true;
+        }
+        if self.io.is_some() {
+            let reading = self.io_reading()?;
+            if let Some((previous, latest)) = self.io.as_mut() {
+                *previous = std::mem::replace(latest, reading);
+            }
+        }
         Ok(())
     }
-    pub fn duration(&self) -> f32 {
-        (self.latest.samplemoment - self.previous.samplemoment)
-            .num_nanoseconds()
-            .unwrap() as f32
-            / 1.0e+9_f32
+    // Read pg_stat_io at every next() from now on (see io_rates), which returns false before PostgreSQL 16
+    pub fn track_io(&mut self) -> Result<bool, PgTpsError> {
+        if self.server_version()? < 160000 {
+            return Ok(false);
+        }
+        let reading = self.io_reading()?;
+        self.io = Some((reading.clone(), reading));
+        Ok(true)
+    }
+    fn io_reading(&mut self) -> Result<IoReading, PgTpsError> {
+        let rows = self
+            .client
+            .query(IO_QUERY, &[])
+            .map_err(PgTpsError::Sampler)?;
+        Ok(IoReading {
+            samplemoment: rows
+                .first()
+                .map(|row| row.get(0))
+                .unwrap_or_else(|| Utc::now().naive_utc()),
+            contexts: rows
+                .iter()
+                .map(|row| (row.get(1), row.get(2), row.get(3), row.get(4)))
+                .collect(),
+        })
+    }
+    // The current value (with unit) of every setting in SETTINGS, in the same order
+    pub fn settings(&mut self) -> Result<Vec<(String, String)>, PgTpsError> {
+        let names: Vec<String> = SETTINGS.iter().map(|name| name.to_string()).collect();
+        let rows = self
+            .client
+            .query(SETTINGS_QUERY, &[&names])
+            .map_err(PgTpsError::Sampler)?;
+        Ok(SETTINGS
+            .iter()
+            .map(|name| {
+                // settings that are unknown to this version of Postgres are shown as '?'
+                let value = rows
+                    .iter()
+                    .find(|row| row.get::<_, String>(0) == *name)
+                    .map(|row| row.get(1))
+                    .unwrap_or_else(|| "?".to_string());
+                (name.to_string(), value)
+            })
+            .collect())
+    }
+    pub fn capabilities(&mut self) -> Result<Capabilities, PgTpsError> {
+        let row = self
+            .client
+            .query_one(CAPABILITIES_QUERY, &[])
+            .map_err(PgTpsError::Sampler)?;
+        Ok(Capabilities {
+            version: row.get(0),
+            version_num: row.get(1),
+            in_recovery: self.in_recovery,
+            max_connections: row.get(2),
+            pg_stat_io: row.get(3),
+            extensions: row.get(4),
+        })
+    }
+    fn server_version(&mut self) -> Result<i32, PgTpsError> {
+        Ok(self
+            .client
+            .query_one("SELECT current_setting('server_version_num')::int", &[])
+            .map_err(PgTpsError::Sampler)?
+            .get(0))
+    }
+    // A snapshot of the backends of the clients (with application_name) that hold a transaction open without running a statement
+    pub fn idle_in_transaction(
+        &mut self,
+        application_name: &str,
+    ) -> Result<(i64, chrono::Duration), PgTpsError> {
+        let row = self
+            .client
+            .query_one(IDLE_IN_TRANSACTION_QUERY, &[&application_name])
+            .map_err(PgTpsError::Sampler)?;
+        let seconds: f64 = row.get(1);
+        Ok((
+            row.get(0),
+            chrono::Duration::microseconds((seconds * 1.0e6) as i64),
+        ))
+    }
+    // A snapshot of what all active backends (other than the sampler) are waiting on
+    pub fn wait_events(&mut self) -> Result<WaitEvents, PgTpsError> {
+        // wait_event_type and wait_event replaced the waiting column in PostgreSQL 9.6
+        let query = match self.server_version()? >= 90600 {
+            true => WAIT_EVENTS_QUERY,
+            false => WAITING_QUERY,
+        };
+        let events = self
+            .client
+            .query(query, &[])
+            .map_err(PgTpsError::Sampler)?
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect();
+        Ok(WaitEvents { events })
     }
-    pub fn wal_per_sec(&self) -> f32 {
-        let wps = (self.latest.wal_bytes - self.previous.wal_bytes) / self.duration();
-        if wps < 0.0 {
-            return -1.0;
+    // Returns false if pg_stat_statements is not installed in the database we connect to
+    pub fn has_statements(&mut self) -> Result<bool, PgTpsError> {
+        let row = self
+            .client
+            .query_one(STATEMENTS_EXTENSION_QUERY, &[])
+            .map_err(PgTpsError::Sampler)?;
+        Ok(row.get(0))
+    }
+    // Clears pg_stat_statements, so that we only see what happened during the benchmark
+    pub fn reset_statements(&mut self) -> Result<(), PgTpsError> {
+        self.client
+            .query("SELECT pg_stat_statements_reset()", &[])
+            .map_err(PgTpsError::Sampler)?;
+        Ok(())
+    }
+    // The statements that took most time since reset_statements
+    pub fn top_statements(&mut self, limit: i64) -> Result<Vec<StatementStats>, PgTpsError> {
+        let version = self.server_version()?;
+        let total_time = match version >= 130000 {
+            true => "total_exec_time",
+            false => "total_time",
+        };
+        let query = TOP_STATEMENTS_QUERY.replace("{total_time}", total_time);
+        Ok(self
+            .client
+            .query(query.as_str(), &[&limit])
+            .map_err(PgTpsError::Sampler)?
+            .iter()
+            .map(|row| StatementStats {
+                query: row.get(0),
+                calls: row.get(1),
+                total_time: row.get(2),
+            })
+            .collect())
+    }
+    // How long it takes until all replicas replayed the wal written so far (see --wal-stress).
+    // On a standby, or before PostgreSQL 10 (which named it replay_location), there are no replicas to tell.
+    pub fn catch_up(&mut self, timeout: chrono::Duration) -> Result<CatchUp, PgTpsError> {
+        if self.in_recovery || self.server_version()? < 100000 {
+            return Ok(CatchUp::NoReplicas);
+        }
+        let start = Utc::now();
+        let lsn: String = self
+            .client
+            .query_one("SELECT pg_current_wal_lsn()::varchar", &[])
+            .map_err(PgTpsError::Sampler)?
+            .get(0);
+        loop {
+            let row = self
+                .client
+                .query_one(REPLAYED_QUERY, &[&lsn])
+                .map_err(PgTpsError::Sampler)?;
+            let (replicas, replayed): (i32, bool) = (row.get(0), row.get(1));
+            if replicas == 0 {
+                return Ok(CatchUp::NoReplicas);
+            }
+            if replayed {
+                return Ok(CatchUp::Replayed(Utc::now() - start));
+            }
+            if Utc::now() - start > timeout {
+                return Ok(CatchUp::TimedOut);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
         }
-        wps
+    }
+    // The size in bytes of an append only table (see --insert) and of its indexes,
+    // and the number of rows in it, which is the last value of its serial primary key (seq)
+    pub fn append_size(&mut self, table: &str) -> Result<(i64, i64, i64), PgTpsError> {
+        let row = self
+            .client
+            .query_one(
+                format!(
+                    "SELECT pg_table_size('{0}'), pg_indexes_size('{0}'), coalesce(max(seq), 0) FROM {0}",
+                    table
+                )
+                .as_str(),
+                &[],
+            )
+            .map_err(PgTpsError::Sampler)?;
+        Ok((row.get(0), row.get(1), row.get(2)))
+    }
+    // Whether a tablespace exists, so that --tablespace fails before any client runs
+    pub fn has_tablespace(&mut self, name: &str) -> Result<bool, PgTpsError> {
+        let row = self
+            .client
+            .query_one(
+                "SELECT count(*) > 0 FROM pg_tablespace WHERE spcname = $1",
+                &[&name],
+            )
+            .map_err(PgTpsError::Sampler)?;
+        Ok(row.get(0))
+    }
+    // The TPS of everything else on the server, sampled over duration before any client runs (see --max-background-tps)
+    pub fn background_tps(&mut self, duration: std::time::Duration) -> Result<f32, PgTpsError> {
+        self.next()?;
+        std::thread::sleep(duration);
+        self.next()?;
+        Ok(self.tps())
+    }
+    // None on a standby (see in_recovery), or when the wal position went backward
+    pub fn wal_per_sec(&self) -> Option<f32> {
+        if self.in_recovery {
+            return None;
+        }
+        self.latest.wal_per_sec_since(&self.previous)
     }
     pub fn tps(&self) -> f32 {
-        (self.latest.num_transactions - self.previous.num_transactions) / self.duration()
+        self.latest.tps_since(&self.previous)
+    }
+    // The io operations per second per context between the latest two readings, or None when pg_stat_io is not tracked
+    pub fn io_rates(&self) -> Option<Vec<IoRates>> {
+        self.io
+            .as_ref()
+            .map(|(previous, latest)| latest.rates_since(previous))
+    }
+    // The percentage of block reads that came from shared buffers, or None without any block reads at all
+    pub fn cache_hit_ratio(&self) -> Option<f64> {
+        self.latest.cache_hit_ratio_since(&self.previous)
     }
 }
 
@@ -69,6 +549,9 @@ struct TransactDataSample {
     lsn: String,
     wal_bytes: f32,
     num_transactions: f32,
+    // double precision, since the difference between two readings of these large counters is what matters
+    blks_hit: f64,
+    blks_read: f64,
 }
 
 impl TransactDataSample {
@@ -78,6 +561,33 @@ impl TransactDataSample {
             lsn: "0/0".to_string(),
             wal_bytes: 0.0_f32,
             num_transactions: 0.0_f32,
+            blks_hit: 0.0,
+            blks_read: 0.0,
+        }
+    }
+    fn duration_since(&self, previous: &TransactDataSample) -> f32 {
+        (self.samplemoment - previous.samplemoment)
+            .num_nanoseconds()
+            .unwrap() as f32
+            / 1.0e+9_f32
+    }
+    // wal_bytes already is the amount of wal since previous, so only the duration comes from previous.
+    // Wal that went backward is a discontinuity (e.g. a failover), not a rate.
+    fn wal_per_sec_since(&self, previous: &TransactDataSample) -> Option<f32> {
+        if self.wal_bytes < 0.0 {
+            return None;
+        }
+        Some(self.wal_bytes / self.duration_since(previous))
+    }
+    fn tps_since(&self, previous: &TransactDataSample) -> f32 {
+        (self.num_transactions - previous.num_transactions) / self.duration_since(previous)
+    }
+    fn cache_hit_ratio_since(&self, previous: &TransactDataSample) -> Option<f64> {
+        let hit = self.blks_hit - previous.blks_hit;
+        let read = self.blks_read - previous.blks_read;
+        match hit + read > 0.0 {
+            true => Some(100.0 * hit / (hit + read)),
+            false => None,
         }
     }
     fn clone(&self) -> TransactDataSample {
@@ -86,6 +596,147 @@ impl TransactDataSample {
             lsn: self.lsn.clone(),
             wal_bytes: self.wal_bytes,
             num_transactions: self.num_transactions,
+            blks_hit: self.blks_hit,
+            blks_read: self.blks_read,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(
+        seconds: i64,
+        lsn: &str,
+        wal_bytes: f32,
+        num_transactions: f32,
+    ) -> TransactDataSample {
+        TransactDataSample {
+            samplemoment: chrono::DateTime::UNIX_EPOCH.naive_utc()
+                + chrono::Duration::seconds(seconds),
+            lsn: lsn.to_string(),
+            wal_bytes,
+            num_transactions,
+            blks_hit: num_transactions as f64 * 10.0,
+            blks_read: num_transactions as f64 / 10.0,
         }
     }
+
+    #[test]
+    fn test_rates() {
+        // the first reading is against 0/0 and zero transactions (the server has run for a while)
+        let first = reading(100, "5/0", 21474836480.0, 1_000_000.0);
+        let second = reading(102, "5/C800", 51200.0, 1_002_000.0);
+        assert_eq!(second.duration_since(&first), 2.0);
+        // the rates only cover the interval between the two readings
+        assert_eq!(second.wal_per_sec_since(&first), Some(25600.0));
+        assert_eq!(second.tps_since(&first), 1000.0);
+        // wal that went backward (e.g. after a failover) cannot be a rate
+        let reset = reading(104, "1/0", -17179920384.0, 1_004_000.0);
+        assert_eq!(reset.wal_per_sec_since(&second), None);
+        // 20000 hits and 200 reads in between
+        let ratio = second.cache_hit_ratio_since(&first).unwrap();
+        assert!((ratio - 100.0 * 20000.0 / 20200.0).abs() < 1e-9);
+        assert_eq!(second.cache_hit_ratio_since(&second), None);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut capabilities = Capabilities {
+            version: "16.2".to_string(),
+            version_num: 160002,
+            in_recovery: false,
+            max_connections: 100,
+            extensions: vec!["pg_stat_statements".to_string(), "plpgsql".to_string()],
+            pg_stat_io: true,
+        };
+        assert_eq!(
+            capabilities.features(),
+            vec![
+                ("benchmark", true),
+                ("call", true),
+                ("pg_stat_statements", true),
+                ("pg_stat_io", true)
+            ]
+        );
+        // an old standby without extensions supports none of them
+        capabilities.version_num = 100023;
+        capabilities.in_recovery = true;
+        capabilities.extensions = vec!["plpgsql".to_string()];
+        capabilities.pg_stat_io = false;
+        assert!(capabilities
+            .features()
+            .iter()
+            .all(|(_, supported)| !supported));
+    }
+
+    #[test]
+    fn test_io_rates() {
+        let io_reading = |seconds: i64, contexts: &[(&str, f64, f64, f64)]| IoReading {
+            samplemoment: chrono::DateTime::UNIX_EPOCH.naive_utc()
+                + chrono::Duration::seconds(seconds),
+            contexts: contexts
+                .iter()
+                .map(|(context, reads, writes, extends)| {
+                    (context.to_string(), *reads, *writes, *extends)
+                })
+                .collect(),
+        };
+        let first = io_reading(
+            100,
+            &[("normal", 1000.0, 500.0, 10.0), ("vacuum", 5.0, 5.0, 0.0)],
+        );
+        let second = io_reading(
+            104,
+            &[
+                ("bulkwrite", 0.0, 40.0, 8.0),
+                ("normal", 1400.0, 700.0, 10.0),
+                ("vacuum", 5.0, 5.0, 0.0),
+            ],
+        );
+        // a context that did no io in between is left out, and a new one counts from 0
+        assert_eq!(
+            second.rates_since(&first),
+            vec![
+                IoRates {
+                    context: "bulkwrite".to_string(),
+                    reads: 0.0,
+                    writes: 10.0,
+                    extends: 2.0
+                },
+                IoRates {
+                    context: "normal".to_string(),
+                    reads: 100.0,
+                    writes: 50.0,
+                    extends: 0.0
+                },
+            ]
+        );
+        assert!(second.rates_since(&second).is_empty());
+    }
+
+    #[test]
+    fn test_wait_events() {
+        let empty = WaitEvents { events: Vec::new() };
+        assert!(empty.dominant().is_none());
+        assert!(empty.fractions().is_empty());
+        let wait_events = WaitEvents {
+            events: vec![
+                ("CPU".to_string(), "CPU".to_string(), 2),
+                ("IO".to_string(), "DataFileRead".to_string(), 3),
+                ("IO".to_string(), "WALSync".to_string(), 2),
+                ("Lock".to_string(), "transactionid".to_string(), 1),
+            ],
+        };
+        assert_eq!(wait_events.dominant().unwrap(), "IO/DataFileRead");
+        assert_eq!(
+            wait_events.fractions(),
+            vec![
+                ("IO".to_string(), 0.625),
+                ("CPU".to_string(), 0.25),
+                ("Lock".to_string(), 0.125)
+            ]
+        );
+    }
 }
@@ -0,0 +1,132 @@
+/*
+Probe reads the CPU utilization of the server from a command (see --cpu-probe),
+since Postgres does not expose the CPU usage of the host it runs on.
+The command runs with sh -c while the clients of a step are running,
+and must print the CPU utilization as a number from 0 to 100 (e.g. `42.5`) to stdout.
+It also reads the CPU utilization of the host we run on ourselves (from /proc/stat, so only on Linux),
+to warn when the clients rather than the server are the bottleneck.
+*/
+use crate::error::PgTpsError;
+use std::process::Command;
+
+// The cpu time (in clock ticks) of all cores of the host since boot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    // The percentage of the cpu time since earlier that the host was busy
+    pub fn utilization_since(&self, earlier: &CpuTimes) -> Option<f64> {
+        let total = self.total.checked_sub(earlier.total).filter(|t| *t > 0)?;
+        let busy = self.busy.saturating_sub(earlier.busy);
+        Some(100.0 * busy as f64 / total as f64)
+    }
+}
+
+// The aggregate cpu line of /proc/stat: user nice system idle iowait irq softirq steal (guest time is part of user)
+fn parse_cpu_times(stat: &str) -> Option<CpuTimes> {
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let ticks: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|ticks| ticks.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if ticks.len() < 5 {
+        return None;
+    }
+    let total: u64 = ticks.iter().sum();
+    Some(CpuTimes {
+        busy: total - ticks[3] - ticks[4],
+        total,
+    })
+}
+
+// The cpu times of the host we run on, or None where there is no /proc/stat
+pub fn host_cpu_times() -> Option<CpuTimes> {
+    parse_cpu_times(&std::fs::read_to_string("/proc/stat").ok()?)
+}
+
+// The percentage a probe printed (surrounding whitespace and a trailing % are allowed)
+fn parse_percentage(output: &str) -> Option<f64> {
+    let output = output.trim();
+    output
+        .strip_suffix('%')
+        .unwrap_or(output)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|percentage| (0.0..=100.0).contains(percentage))
+}
+
+// Run the probe command and return the CPU utilization (percent) it printed
+pub fn cpu_utilization(command: &str) -> Result<f64, PgTpsError> {
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    if !output.status.success() {
+        return Err(PgTpsError::Config(format!(
+            "invalid value for cpu_probe: {} failed ({}): {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_percentage(&stdout).ok_or_else(|| {
+        PgTpsError::Config(format!(
+            "invalid value for cpu_probe: {} printed '{}', which is not a number from 0 to 100",
+            command,
+            stdout.trim()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_percentage() {
+        assert_eq!(parse_percentage("42.5\n"), Some(42.5));
+        assert_eq!(parse_percentage(" 70 % "), Some(70.0));
+        assert_eq!(parse_percentage("0"), Some(0.0));
+        assert_eq!(parse_percentage("100.5"), None);
+        assert_eq!(parse_percentage("-1"), None);
+        assert_eq!(parse_percentage("busy"), None);
+        assert_eq!(parse_percentage(""), None);
+    }
+
+    #[test]
+    fn test_cpu_utilization() {
+        assert_eq!(cpu_utilization("echo 55.5").unwrap(), 55.5);
+        assert!(matches!(
+            cpu_utilization("echo lots"),
+            Err(PgTpsError::Config(_))
+        ));
+        assert!(matches!(
+            cpu_utilization("exit 3"),
+            Err(PgTpsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_cpu_times() {
+        let earlier =
+            parse_cpu_times("cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 100 0 50 800 50 0 0 0 0 0\n")
+                .unwrap();
+        assert_eq!(
+            earlier,
+            CpuTimes {
+                busy: 150,
+                total: 1000
+            }
+        );
+        let later = parse_cpu_times("cpu  250 0 100 850 50 0 0 0\n").unwrap();
+        // 200 of the 250 ticks in between were busy
+        assert_eq!(later.utilization_since(&earlier), Some(80.0));
+        assert_eq!(earlier.utilization_since(&earlier), None);
+        assert_eq!(parse_cpu_times("cpu0 1 2 3 4 5\n"), None);
+        assert_eq!(parse_cpu_times("cpu  1 2 x 4 5\n"), None);
+    }
+}
@@ -0,0 +1,93 @@
+/*
+Progress estimates how long the rest of a sweep takes (see --progress),
+from how long the completed steps took to stabilize.
+Since steps with more clients often take longer, --progress-weighted scales that by the number of clients of every step.
+*/
+use std::time::Duration;
+
+// width of the progress bar in characters
+const BAR_WIDTH: usize = 30;
+
+// The time the remaining steps take, from the average duration of the completed steps
+// (the first durations.len() of steps) times the number of remaining steps.
+// Weighted, every step takes time in proportion to its number of clients instead.
+pub fn eta(steps: &[u32], durations: &[Duration], weighted: bool) -> Option<Duration> {
+    if durations.is_empty() || durations.len() > steps.len() {
+        return None;
+    }
+    let (completed, remaining) = steps.split_at(durations.len());
+    let elapsed: Duration = durations.iter().sum();
+    if !weighted {
+        return Some(elapsed / durations.len() as u32 * remaining.len() as u32);
+    }
+    let completed: u64 = completed.iter().map(|clients| *clients as u64).sum();
+    let remaining: u64 = remaining.iter().map(|clients| *clients as u64).sum();
+    if completed == 0 {
+        return None;
+    }
+    Some(elapsed.mul_f64(remaining as f64 / completed as f64))
+}
+
+// A line like `[#########.....................]  3/10 steps (30%), eta 1m05s`
+pub fn bar(completed: usize, total: usize, eta: Option<Duration>) -> String {
+    let fraction = match total {
+        0 => 1.0,
+        _ => completed.min(total) as f64 / total as f64,
+    };
+    let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+    let eta = match eta {
+        Some(eta) => {
+            let seconds = eta.as_secs_f64().round() as u64;
+            match seconds / 60 {
+                0 => format!("{}s", seconds),
+                minutes => format!("{}m{:02}s", minutes, seconds % 60),
+            }
+        }
+        None => "?".to_string(),
+    };
+    format!(
+        "[{}{}] {:>2}/{} steps ({:.0}%), eta {}",
+        "#".repeat(filled),
+        ".".repeat(BAR_WIDTH - filled),
+        completed,
+        total,
+        100.0 * fraction,
+        eta
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta() {
+        let steps = [1, 2, 3, 5, 8];
+        let secs = Duration::from_secs;
+        assert_eq!(eta(&steps, &[], false), None);
+        // 2 steps averaging 15s, 3 to go
+        assert_eq!(eta(&steps, &[secs(10), secs(20)], false), Some(secs(45)));
+        // 30s for 3 clients, 16 clients to go
+        assert_eq!(eta(&steps, &[secs(10), secs(20)], true), Some(secs(160)));
+        let all = [secs(1); 5];
+        assert_eq!(eta(&steps, &all, false), Some(secs(0)));
+        assert_eq!(eta(&steps, &all, true), Some(secs(0)));
+        assert_eq!(eta(&steps, &[secs(1); 6], false), None);
+        assert_eq!(eta(&[0, 1], &[secs(1)], true), None);
+    }
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(
+            bar(3, 10, Some(Duration::from_secs(65))),
+            format!(
+                "[{}{}]  3/10 steps (30%), eta 1m05s",
+                "#".repeat(9),
+                ".".repeat(21)
+            )
+        );
+        assert!(bar(0, 10, None).ends_with("]  0/10 steps (0%), eta ?"));
+        assert!(bar(10, 10, Some(Duration::from_millis(400))).ends_with("(100%), eta 0s"));
+        assert!(bar(0, 0, None).starts_with(&format!("[{}]", "#".repeat(BAR_WIDTH))));
+    }
+}
@@ -0,0 +1,278 @@
+/*
+Prometheus exposes the results in the Prometheus text format.
+With --textfile the final results are written once at the end of the run,
+so that the textfile collector of node_exporter can pick them up.
+While running, the textfile is rewritten at every phase transition with the progress of the sweep.
+With --openmetrics it is written in the OpenMetrics text format instead, which (with --exemplars)
+links the samples of every number of clients to the trace id of its slowest tagged transaction.
+*/
+use crate::aggregate::Aggregate;
+use crate::error::PgTpsError;
+use std::collections::BTreeMap;
+use std::fs;
+
+// name, type and help of every metric we expose
+pub const METRICS: [(&str, &str, &str); 3] = [
+    (
+        "pg_tps_optimizer_tps",
+        "gauge",
+        "Transactions per second (median over all runs) per number of clients.",
+    ),
+    (
+        "pg_tps_optimizer_latency_seconds",
+        "gauge",
+        "Transaction latency in seconds (median over all runs) per number of clients.",
+    ),
+    (
+        "pg_tps_optimizer_runs",
+        "gauge",
+        "Number of runs the other metrics are based on.",
+    ),
+];
+
+// What a sweep is doing right now, as exposed by pg_tps_optimizer_phase
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    // not running a step (e.g. in between bursts, or finished)
+    Idle = 0,
+    // adding clients and waiting for them to connect
+    Ramp = 1,
+    // waiting for the results to stabilize
+    Warmup = 2,
+    // measuring for a fixed duration (see --burst-on)
+    Measure = 3,
+}
+
+// How far along a sweep is
+pub struct Progress {
+    pub steps_total: usize,
+    pub steps_completed: usize,
+    pub phase: Phase,
+}
+
+// The progress of a sweep in the Prometheus text format
+pub fn progress(progress: &Progress) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for (name, help, value) in [
+        (
+            "pg_tps_optimizer_steps_total",
+            "Number of steps (numbers of clients) in the sweep.",
+            progress.steps_total,
+        ),
+        (
+            "pg_tps_optimizer_steps_completed",
+            "Number of steps of the sweep that have a result.",
+            progress.steps_completed,
+        ),
+        (
+            "pg_tps_optimizer_phase",
+            "What the sweep is doing: 0=idle, 1=ramp, 2=warmup, 3=measure.",
+            progress.phase as usize,
+        ),
+    ] {
+        lines.push(format!("# HELP {} {}", name, help));
+        lines.push(format!("# TYPE {} gauge", name));
+        lines.push(format!("{} {}", name, value));
+    }
+    lines.push("".to_string());
+    lines.join("\n")
+}
+
+// label values may not contain unescaped backslashes, double quotes or newlines
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// The final results of every target in the Prometheus text format,
+// labeled by number of clients and the labels of the target (e.g. host and tags)
+pub fn textfile(targets: &[(BTreeMap<String, String>, Aggregate)]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for (name, kind, help) in METRICS {
+        lines.push(format!("# HELP {} {}", name, help));
+        lines.push(format!("# TYPE {} {}", name, kind));
+        for (labels, aggregate) in targets {
+            let labels: String = labels
+                .iter()
+                .map(|(key, value)| format!(",{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            for (clients, tps, latency) in aggregate.summaries() {
+                let value = match name {
+                    "pg_tps_optimizer_tps" => tps.median,
+                    "pg_tps_optimizer_latency_seconds" => latency.median / 1e6,
+                    _ => tps.runs as f64,
+                };
+                lines.push(format!(
+                    "{}{{clients=\"{}\"{}}} {}",
+                    name, clients, labels, value
+                ));
+            }
+        }
+    }
+    lines.push("".to_string());
+    lines.join("\n")
+}
+
+// The number of samples per number of clients as a counter, since OpenMetrics only allows exemplars on counters
+// and histograms, with the slowest tagged transaction (its trace id and latency in seconds) as exemplar
+pub fn exemplars(targets: &[(BTreeMap<String, String>, Aggregate)]) -> String {
+    let name = "pg_tps_optimizer_samples";
+    let mut lines: Vec<String> = vec![
+        format!(
+            "# HELP {} Timeslices (of 200ms) the results are based on, with the slowest traced transaction as exemplar.",
+            name
+        ),
+        format!("# TYPE {} counter", name),
+    ];
+    for (labels, aggregate) in targets {
+        let labels: String = labels
+            .iter()
+            .map(|(key, value)| format!(",{}=\"{}\"", key, escape_label(value)))
+            .collect();
+        for (clients, _, _) in aggregate.summaries() {
+            let results = aggregate.results(clients);
+            let samples: usize = results.iter().map(|result| result.samples).sum();
+            let exemplar = results
+                .iter()
+                .filter_map(|result| result.trace)
+                .max_by_key(|(_, latency)| *latency)
+                .map(|(trace_id, latency)| {
+                    format!(
+                        " # {{trace_id=\"{:032x}\"}} {}",
+                        trace_id,
+                        latency.num_microseconds().unwrap_or(0) as f64 / 1e6
+                    )
+                })
+                .unwrap_or_default();
+            lines.push(format!(
+                "{}_total{{clients=\"{}\"{}}} {}{}",
+                name, clients, labels, samples, exemplar
+            ));
+        }
+    }
+    lines.push("".to_string());
+    lines.join("\n")
+}
+
+// The OpenMetrics text format ends with an EOF marker, which the Prometheus text format does not have
+pub fn terminate(contents: String, openmetrics: bool) -> String {
+    match openmetrics {
+        true => contents + "# EOF\n",
+        false => contents,
+    }
+}
+
+// Write to a temp file next to path and rename it,
+// so that the collector never reads a half written file
+pub fn write_textfile(path: &str, contents: &str) -> Result<(), PgTpsError> {
+    let temp_path = format!("{}.{}.tmp", path, std::process::id());
+    fs::write(&temp_path, contents)?;
+    if let Err(err) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::threader::TestResult;
+    use chrono::Duration;
+
+    fn aggregate(tps: f64) -> Aggregate {
+        let mut aggregate = Aggregate::new();
+        aggregate.add(
+            8,
+            &TestResult {
+                stable: true,
+                tps,
+                latency: Duration::microseconds(2500),
+                overhead: Duration::zero(),
+                rollback_ratio: 0.0,
+                samples: 10,
+                stddev: None,
+                window: None,
+                trace: None,
+                confidence: None,
+            },
+        );
+        aggregate
+    }
+
+    #[test]
+    fn test_textfile() {
+        let labels = BTreeMap::from([("host".to_string(), "db\"1".to_string())]);
+        let text = textfile(&[(labels, aggregate(1500.0))]);
+        assert!(text.starts_with("# HELP pg_tps_optimizer_tps "));
+        assert!(text.contains("# TYPE pg_tps_optimizer_tps gauge\n"));
+        assert!(text.contains("pg_tps_optimizer_tps{clients=\"8\",host=\"db\\\"1\"} 1500\n"));
+        assert!(text
+            .contains("pg_tps_optimizer_latency_seconds{clients=\"8\",host=\"db\\\"1\"} 0.0025\n"));
+        assert!(text.contains("pg_tps_optimizer_runs{clients=\"8\",host=\"db\\\"1\"} 1\n"));
+        // multiple targets share the HELP and TYPE lines
+        let targets: Vec<(BTreeMap<String, String>, Aggregate)> = ["a", "b"]
+            .iter()
+            .map(|target| {
+                let labels = BTreeMap::from([
+                    ("label".to_string(), "before-index".to_string()),
+                    ("target".to_string(), target.to_string()),
+                ]);
+                (labels, aggregate(1000.0))
+            })
+            .collect();
+        let progress_text = progress(&Progress {
+            steps_total: 10,
+            steps_completed: 4,
+            phase: Phase::Warmup,
+        });
+        assert!(progress_text.contains("# TYPE pg_tps_optimizer_phase gauge\n"));
+        assert!(progress_text.contains("\npg_tps_optimizer_steps_total 10\n"));
+        assert!(progress_text.contains("\npg_tps_optimizer_steps_completed 4\n"));
+        assert!(progress_text.contains("\npg_tps_optimizer_phase 2\n"));
+        let multi = textfile(&targets);
+        assert!(!terminate(multi.clone(), false).contains("# EOF"));
+        assert!(terminate(multi.clone(), true).ends_with("\n# EOF\n"));
+        assert_eq!(multi.matches("# HELP pg_tps_optimizer_runs ").count(), 1);
+        assert!(multi.contains(
+            "pg_tps_optimizer_runs{clients=\"8\",label=\"before-index\",target=\"a\"} 1\n"
+        ));
+        assert!(multi.contains(
+            "pg_tps_optimizer_runs{clients=\"8\",label=\"before-index\",target=\"b\"} 1\n"
+        ));
+
+        let dir = std::env::temp_dir().join(format!("pg_tps_optimizer_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pg_tps_optimizer.prom");
+        write_textfile(path.to_str().unwrap(), &text).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        // the temp file is gone after the rename
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_exemplars() {
+        let mut traced = aggregate(1500.0);
+        let mut result = traced.results(8)[0];
+        result.trace = Some((
+            0x4bf92f3577b34da6a3ce929d0e0e4736,
+            Duration::microseconds(3100),
+        ));
+        traced.add(8, &result);
+        result.trace = Some((1, Duration::microseconds(1200)));
+        traced.add(8, &result);
+        let labels = BTreeMap::from([("host".to_string(), "db1".to_string())]);
+        let text = exemplars(&[(labels.clone(), traced)]);
+        assert!(text.contains("# TYPE pg_tps_optimizer_samples counter\n"));
+        // the slowest of all runs, with the samples of all runs
+        assert!(text.contains(
+            "pg_tps_optimizer_samples_total{clients=\"8\",host=\"db1\"} 30 # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 0.0031\n"
+        ));
+        // no exemplar without traced transactions
+        let text = exemplars(&[(labels, aggregate(1500.0))]);
+        assert!(text.contains("pg_tps_optimizer_samples_total{clients=\"8\",host=\"db1\"} 10\n"));
+    }
+}
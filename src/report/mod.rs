@@ -0,0 +1,600 @@
+/*
+Report prints the results table of a sweep (one line per step) to stdout,
+either as the ascii table we always printed, or as a GitHub flavored Markdown table (see --output-format),
+which can be pasted into a pull request or an issue as is.
+The same goes for the table of --monitor (one line per interval),
+for the table of --target-latency and --target-cpu-utilization (one line per probe),
+for the summary of --repeat (one line per number of clients) and for the top statements of --pg-stat-statements.
+*/
+use crate::error::PgTpsError;
+
+// The width of every column of the ascii table, and of the optional column without the round trip time
+const WIDTHS: [usize; 11] = [19, 7, 6, 7, 13, 9, 9, 11, 9, 9, 9];
+const RTT_WIDTH: usize = 9;
+
+// The columns of the Markdown table, and of the optional column without the round trip time
+const MARKDOWN_COLUMNS: [&str; 11] = [
+    "Date time",
+    "Clients",
+    "Stable",
+    "Samples",
+    "TPS",
+    "Latency (usec)",
+    "Commit (usec)",
+    "TPS/Latency",
+    "Postgres TPS",
+    "wal kB/s",
+    "wal/xact bytes",
+];
+const MARKDOWN_RTT_COLUMN: &str = "Latency w/o RTT (usec)";
+
+// The columns of --monitor, with their width in the ascii table
+const MONITOR_COLUMNS: [(&str, usize); 4] = [
+    ("Date time", 19),
+    ("TPS", 11),
+    ("wal kB/s", 11),
+    ("Cache hit %", 11),
+];
+
+// The columns of a target search (as 2 lines of the ascii header), with their width in the ascii table,
+// and the column for the CPU utilization, which only --target-cpu-utilization has (before within target)
+const SEARCH_COLUMNS: [(&str, &str, usize); 6] = [
+    ("Date time", "", 19),
+    ("Clients", "", 7),
+    ("Stable", "", 6),
+    ("TPS", "", 13),
+    ("Latency", "(usec)", 9),
+    ("Within", "target", 6),
+];
+const SEARCH_CPU_COLUMN: (&str, &str, usize) = ("CPU", "(%)", 7);
+
+// The columns of the summary of --repeat, with their width in the ascii table
+const AGGREGATE_COLUMNS: [(&str, usize); 10] = [
+    ("Clients", 7),
+    ("Runs", 4),
+    ("TPS median", 9),
+    ("TPS mean", 9),
+    ("TPS stddev", 9),
+    ("TPS CV %", 9),
+    ("Latency median (usec)", 9),
+    ("Latency mean (usec)", 9),
+    ("Latency stddev (usec)", 9),
+    ("Latency CV %", 9),
+];
+
+// The columns of the top statements, of which the query (the last one) has no width since it is not aligned
+const STATEMENTS_COLUMNS: [(&str, usize); 3] =
+    [("Calls", 7), ("Total (ms)", 11), ("Mean (ms)", 11)];
+const STATEMENTS_QUERY_COLUMN: &str = "Query";
+
+// A line of an ascii table, with every cell aligned right to the width of its column
+fn ascii_row<'a>(cells: &[String], widths: impl Iterator<Item = &'a usize>) -> String {
+    let cells: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!(" {:>1$} |", cell, width))
+        .collect();
+    format!("|{}", cells.concat())
+}
+
+fn markdown_row(cells: &[String]) -> String {
+    format!("| {} |", cells.join(" | "))
+}
+
+// A separator line of an ascii table, with a column for every width
+fn ascii_separator<'a>(widths: impl Iterator<Item = &'a usize>) -> String {
+    let separator: String = widths
+        .map(|width| format!("{}|", "-".repeat(width + 2)))
+        .collect();
+    format!("|{}", separator)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Markdown,
+}
+
+impl OutputFormat {
+    pub fn from_string(format: &str) -> Result<OutputFormat, PgTpsError> {
+        match format.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "markdown" => Ok(OutputFormat::Markdown),
+            _ => Err(PgTpsError::Config(format!(
+                "invalid value for output_format: {} is not one of table or markdown",
+                format
+            ))),
+        }
+    }
+}
+
+pub struct ResultsTable {
+    format: OutputFormat,
+    // with --measure-rtt every line gets an extra column for the latency without the round trip time
+    rtt: bool,
+}
+
+impl ResultsTable {
+    pub fn new(format: OutputFormat, rtt: bool) -> ResultsTable {
+        ResultsTable { format, rtt }
+    }
+    pub fn header(&self) -> Vec<String> {
+        match self.format {
+            OutputFormat::Table => {
+                let rtt_columns = match self.rtt {
+                    true => [
+                        "-----------|",
+                        "  w/o RTT  |",
+                        "-----------|",
+                        "  Latency  |",
+                        "   (usec)  |",
+                        "-----------|",
+                    ],
+                    false => [""; 6],
+                };
+                vec![
+                    format!("|---------------------|---------|--------|---------|-----------------------------------------------------|-----------------------------------|{}", rtt_columns[0]),
+                    format!("| Date       time     | Clients | Stable | Samples |                       Performance                   |              Postgres             |{}", rtt_columns[1]),
+                    format!("|                     |         |        |         |---------------|-----------|-----------|-------------|-----------|-----------|-----------|{}", rtt_columns[2]),
+                    format!("|                     |         |        |         |      TPS      |  Latency  |  Commit   | TPS/Latency |   TPS     |    wal    | wal/xact  |{}", rtt_columns[3]),
+                    format!("|                     |         |        |         |               |   (usec)  |   (usec)  |             |           |    kB/s   |   bytes   |{}", rtt_columns[4]),
+                    self.separator(),
+                ]
+            }
+            OutputFormat::Markdown => {
+                let mut columns = MARKDOWN_COLUMNS.to_vec();
+                if self.rtt {
+                    columns.push(MARKDOWN_RTT_COLUMN);
+                }
+                // numeric columns align right, date time and stable (yes / no) align left
+                let alignments: Vec<&str> = (0..columns.len())
+                    .map(|i| match i {
+                        0 | 2 => "---",
+                        _ => "---:",
+                    })
+                    .collect();
+                vec![
+                    markdown_row(&columns.iter().map(|c| c.to_string()).collect::<Vec<_>>()),
+                    format!("|{}|", alignments.join("|")),
+                ]
+            }
+        }
+    }
+    // A line of the table, with a cell for every column
+    pub fn row(&self, cells: &[String]) -> String {
+        match self.format {
+            OutputFormat::Table => {
+                ascii_row(cells, WIDTHS.iter().chain(self.rtt.then_some(&RTT_WIDTH)))
+            }
+            OutputFormat::Markdown => markdown_row(cells),
+        }
+    }
+    // Markdown tables have no closing line
+    pub fn footer(&self) -> Option<String> {
+        match self.format {
+            OutputFormat::Table => Some(self.separator()),
+            OutputFormat::Markdown => None,
+        }
+    }
+    fn separator(&self) -> String {
+        let rtt_column = match self.rtt {
+            true => "-----------|",
+            false => "",
+        };
+        format!("|---------------------|---------|--------|---------|---------------|-----------|-----------|-------------|-----------|-----------|-----------|{}", rtt_column)
+    }
+}
+
+// The table of --monitor, which has no footer since it runs until interrupted
+pub struct MonitorTable {
+    format: OutputFormat,
+}
+
+impl MonitorTable {
+    pub fn new(format: OutputFormat) -> MonitorTable {
+        MonitorTable { format }
+    }
+    pub fn header(&self) -> Vec<String> {
+        let names: Vec<String> = MONITOR_COLUMNS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        match self.format {
+            OutputFormat::Table => {
+                let separator: String = MONITOR_COLUMNS
+                    .iter()
+                    .map(|(_, width)| format!("{}|", "-".repeat(width + 2)))
+                    .collect();
+                vec![
+                    format!("|{}", separator),
+                    ascii_row(&names, MONITOR_COLUMNS.iter().map(|(_, width)| width)),
+                    format!("|{}", separator),
+                ]
+            }
+            OutputFormat::Markdown => {
+                vec![markdown_row(&names), "|---|---:|---:|---:|".to_string()]
+            }
+        }
+    }
+    pub fn row(&self, cells: &[String]) -> String {
+        match self.format {
+            OutputFormat::Table => ascii_row(cells, MONITOR_COLUMNS.iter().map(|(_, width)| width)),
+            OutputFormat::Markdown => markdown_row(cells),
+        }
+    }
+}
+
+// The table of a search for the highest number of clients within target
+pub struct SearchTable {
+    format: OutputFormat,
+    // with --target-cpu-utilization every line gets an extra column for the CPU utilization
+    cpu: bool,
+}
+
+impl SearchTable {
+    pub fn new(format: OutputFormat, cpu: bool) -> SearchTable {
+        SearchTable { format, cpu }
+    }
+    fn columns(&self) -> Vec<(&'static str, &'static str, usize)> {
+        let mut columns = SEARCH_COLUMNS.to_vec();
+        if self.cpu {
+            columns.insert(columns.len() - 1, SEARCH_CPU_COLUMN);
+        }
+        columns
+    }
+    pub fn header(&self) -> Vec<String> {
+        let columns = self.columns();
+        match self.format {
+            OutputFormat::Table => {
+                let line = |cell: fn(&(&str, &str, usize)) -> String| {
+                    format!("|{}", columns.iter().map(cell).collect::<String>())
+                };
+                vec![
+                    self.separator(),
+                    line(|(first, _, width)| format!(" {:^1$} |", first, width)),
+                    line(|(_, second, width)| format!(" {:^1$} |", second, width)),
+                    self.separator(),
+                ]
+            }
+            OutputFormat::Markdown => {
+                let names: Vec<String> = columns
+                    .iter()
+                    .map(|(first, second, _)| format!("{} {}", first, second).trim().to_string())
+                    .collect();
+                // numeric columns align right, date time, stable and within target (yes / no) align left
+                let last = names.len() - 1;
+                let alignments: Vec<&str> = (0..names.len())
+                    .map(|i| match i {
+                        0 | 2 => "---",
+                        _ if i == last => "---",
+                        _ => "---:",
+                    })
+                    .collect();
+                vec![markdown_row(&names), format!("|{}|", alignments.join("|"))]
+            }
+        }
+    }
+    // A line of the table, with a cell for every column
+    pub fn row(&self, cells: &[String]) -> String {
+        match self.format {
+            OutputFormat::Table => {
+                ascii_row(cells, self.columns().iter().map(|(_, _, width)| width))
+            }
+            OutputFormat::Markdown => markdown_row(cells),
+        }
+    }
+    // Markdown tables have no closing line
+    pub fn footer(&self) -> Option<String> {
+        match self.format {
+            OutputFormat::Table => Some(self.separator()),
+            OutputFormat::Markdown => None,
+        }
+    }
+    fn separator(&self) -> String {
+        let separator: String = self
+            .columns()
+            .iter()
+            .map(|(_, _, width)| format!("{}|", "-".repeat(width + 2)))
+            .collect();
+        format!("|{}", separator)
+    }
+}
+
+// The summary of --repeat, with the spread of TPS and latency over all runs per number of clients
+pub struct AggregateTable {
+    format: OutputFormat,
+}
+
+impl AggregateTable {
+    pub fn new(format: OutputFormat) -> AggregateTable {
+        AggregateTable { format }
+    }
+    pub fn header(&self) -> Vec<String> {
+        match self.format {
+            OutputFormat::Table => vec![
+                "|---------|------|-----------------------------------------------|-----------------------------------------------|".to_string(),
+                "| Clients | Runs |                      TPS                      |                 Latency (usec)                |".to_string(),
+                "|         |      |-----------|-----------|-----------|-----------|-----------|-----------|-----------|-----------|".to_string(),
+                "|         |      |  median   |   mean    |  stddev   |   CV %    |  median   |   mean    |  stddev   |   CV %    |".to_string(),
+                self.separator(),
+            ],
+            OutputFormat::Markdown => {
+                let names: Vec<String> = AGGREGATE_COLUMNS
+                    .iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect();
+                vec![
+                    markdown_row(&names),
+                    format!("|{}|", vec!["---:"; names.len()].join("|")),
+                ]
+            }
+        }
+    }
+    pub fn row(&self, cells: &[String]) -> String {
+        match self.format {
+            OutputFormat::Table => {
+                ascii_row(cells, AGGREGATE_COLUMNS.iter().map(|(_, width)| width))
+            }
+            OutputFormat::Markdown => markdown_row(cells),
+        }
+    }
+    // Markdown tables have no closing line
+    pub fn footer(&self) -> Option<String> {
+        match self.format {
+            OutputFormat::Table => Some(self.separator()),
+            OutputFormat::Markdown => None,
+        }
+    }
+    fn separator(&self) -> String {
+        ascii_separator(AGGREGATE_COLUMNS.iter().map(|(_, width)| width))
+    }
+}
+
+// The top statements of --pg-stat-statements, where the query (the last cell) is not aligned
+pub struct StatementsTable {
+    format: OutputFormat,
+}
+
+impl StatementsTable {
+    pub fn new(format: OutputFormat) -> StatementsTable {
+        StatementsTable { format }
+    }
+    pub fn header(&self) -> Vec<String> {
+        let names: Vec<String> = STATEMENTS_COLUMNS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .chain([STATEMENTS_QUERY_COLUMN.to_string()])
+            .collect();
+        match self.format {
+            OutputFormat::Table => vec![
+                self.separator(),
+                format!(
+                    "{} {}",
+                    ascii_row(&names, STATEMENTS_COLUMNS.iter().map(|(_, width)| width)),
+                    STATEMENTS_QUERY_COLUMN
+                ),
+                self.separator(),
+            ],
+            OutputFormat::Markdown => {
+                vec![markdown_row(&names), "|---:|---:|---:|---|".to_string()]
+            }
+        }
+    }
+    // A line of the table, where a query can hold anything, so in Markdown pipes are escaped and newlines become spaces
+    pub fn row(&self, cells: &[String]) -> String {
+        let (query, cells) = cells.split_last().unwrap();
+        match self.format {
+            OutputFormat::Table => format!(
+                "{} {}",
+                ascii_row(cells, STATEMENTS_COLUMNS.iter().map(|(_, width)| width)),
+                query
+            ),
+            OutputFormat::Markdown => {
+                let mut cells = cells.to_vec();
+                cells.push(query.replace('|', "\\|").replace(['\n', '\r'], " "));
+                markdown_row(&cells)
+            }
+        }
+    }
+    pub fn footer(&self) -> Option<String> {
+        match self.format {
+            OutputFormat::Table => Some(self.separator()),
+            OutputFormat::Markdown => None,
+        }
+    }
+    // The query column is open ended, so it gets a fixed amount of dashes
+    fn separator(&self) -> String {
+        format!(
+            "{}{}",
+            ascii_separator(STATEMENTS_COLUMNS.iter().map(|(_, width)| width)),
+            "-".repeat(52)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(rtt: bool) -> Vec<String> {
+        let mut cells: Vec<String> = [
+            "2026-10-18 01:50:30",
+            "3",
+            "yes",
+            "11",
+            "3374.197",
+            "1780.0",
+            "0.0",
+            "1.896",
+            "3542.698",
+            "202153",
+            "57.1",
+        ]
+        .iter()
+        .map(|cell| cell.to_string())
+        .collect();
+        if rtt {
+            cells.push("1700.0".to_string());
+        }
+        cells
+    }
+
+    #[test]
+    fn test_output_format() {
+        assert_eq!(
+            OutputFormat::from_string("Markdown").unwrap(),
+            OutputFormat::Markdown
+        );
+        assert_eq!(
+            OutputFormat::from_string("table").unwrap(),
+            OutputFormat::Table
+        );
+        assert!(matches!(
+            OutputFormat::from_string("html"),
+            Err(PgTpsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_table() {
+        for rtt in [false, true] {
+            let table = ResultsTable::new(OutputFormat::Table, rtt);
+            let header = table.header();
+            let row = table.row(&cells(rtt));
+            // every line of the table is equally wide
+            assert!(header.iter().all(|line| line.len() == row.len()));
+            assert_eq!(table.footer(), Some(header[5].clone()));
+        }
+        let row = ResultsTable::new(OutputFormat::Table, false).row(&cells(false));
+        assert!(
+            row.starts_with("| 2026-10-18 01:50:30 |       3 |    yes |      11 |      3374.197 |")
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        let table = ResultsTable::new(OutputFormat::Markdown, true);
+        let header = table.header();
+        assert_eq!(header.len(), 2);
+        assert!(header[0].starts_with("| Date time | Clients | Stable | Samples | TPS |"));
+        assert!(header[0].ends_with("| Latency w/o RTT (usec) |"));
+        assert!(header[1].starts_with("|---|---:|---|---:|---:|"));
+        // as many alignments as columns
+        assert_eq!(
+            header[1].matches('|').count(),
+            header[0].matches(" | ").count() + 2
+        );
+        assert_eq!(
+            table.row(&cells(true)),
+            "| 2026-10-18 01:50:30 | 3 | yes | 11 | 3374.197 | 1780.0 | 0.0 | 1.896 | 3542.698 | 202153 | 57.1 | 1700.0 |"
+        );
+        assert_eq!(table.footer(), None);
+    }
+
+    #[test]
+    fn test_monitor() {
+        let cells: Vec<String> = ["2026-10-18 01:50:30", "3374.197", "220.5", "99.82"]
+            .iter()
+            .map(|cell| cell.to_string())
+            .collect();
+        let table = MonitorTable::new(OutputFormat::Table);
+        let row = table.row(&cells);
+        assert_eq!(
+            row,
+            "| 2026-10-18 01:50:30 |    3374.197 |       220.5 |       99.82 |"
+        );
+        assert!(table.header().iter().all(|line| line.len() == row.len()));
+        let table = MonitorTable::new(OutputFormat::Markdown);
+        assert_eq!(
+            table.header()[0],
+            "| Date time | TPS | wal kB/s | Cache hit % |"
+        );
+        assert_eq!(
+            table.row(&cells),
+            "| 2026-10-18 01:50:30 | 3374.197 | 220.5 | 99.82 |"
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let cells: Vec<String> = ["2026-10-18 01:50:30", "3", "yes", "3374.197", "1780.0"]
+            .iter()
+            .map(|cell| cell.to_string())
+            .collect();
+        let table = SearchTable::new(OutputFormat::Table, false);
+        let mut within = cells.clone();
+        within.push("yes".to_string());
+        let row = table.row(&within);
+        assert_eq!(
+            row,
+            "| 2026-10-18 01:50:30 |       3 |    yes |      3374.197 |    1780.0 |    yes |"
+        );
+        assert!(table.header().iter().all(|line| line.len() == row.len()));
+        assert_eq!(table.footer(), Some(table.header()[0].clone()));
+        let table = SearchTable::new(OutputFormat::Markdown, true);
+        let mut cpu = cells;
+        cpu.extend(["42.0".to_string(), "no".to_string()]);
+        assert_eq!(
+            table.header(),
+            vec![
+                "| Date time | Clients | Stable | TPS | Latency (usec) | CPU (%) | Within target |",
+                "|---|---:|---|---:|---:|---:|---|",
+            ]
+        );
+        assert_eq!(
+            table.row(&cpu),
+            "| 2026-10-18 01:50:30 | 3 | yes | 3374.197 | 1780.0 | 42.0 | no |"
+        );
+        assert_eq!(table.footer(), None);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let cells: Vec<String> = [
+            "3", "5", "3374.197", "3370.000", "12.500", "0.37", "1780.0", "1781.2", "9.1", "0.51",
+        ]
+        .iter()
+        .map(|cell| cell.to_string())
+        .collect();
+        let table = AggregateTable::new(OutputFormat::Table);
+        let row = table.row(&cells);
+        assert!(row.starts_with("|       3 |    5 |  3374.197 |  3370.000 |"));
+        assert!(table.header().iter().all(|line| line.len() == row.len()));
+        assert_eq!(table.footer(), Some(table.header()[4].clone()));
+        let table = AggregateTable::new(OutputFormat::Markdown);
+        assert!(table.header()[0].starts_with("| Clients | Runs | TPS median |"));
+        assert_eq!(
+            table.header()[1].matches("---:").count(),
+            AGGREGATE_COLUMNS.len()
+        );
+        assert_eq!(table.row(&cells), markdown_row(&cells));
+        assert_eq!(table.footer(), None);
+    }
+
+    #[test]
+    fn test_statements() {
+        let cells: Vec<String> = ["1200", "35.250", "0.029", "select a || b\nfrom t"]
+            .iter()
+            .map(|cell| cell.to_string())
+            .collect();
+        let table = StatementsTable::new(OutputFormat::Table);
+        assert_eq!(
+            table.row(&cells),
+            "|    1200 |      35.250 |       0.029 | select a || b\nfrom t"
+        );
+        assert_eq!(
+            table.header()[1],
+            "|   Calls |  Total (ms) |   Mean (ms) | Query"
+        );
+        let table = StatementsTable::new(OutputFormat::Markdown);
+        assert_eq!(
+            table.header()[0],
+            "| Calls | Total (ms) | Mean (ms) | Query |"
+        );
+        // a pipe or newline in the query would break the Markdown table
+        assert_eq!(
+            table.row(&cells),
+            "| 1200 | 35.250 | 0.029 | select a \\|\\| b from t |"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::PgTpsError;
 use crate::threader::sample::ParallelSamples;
 use crate::threader::worker::Worker;
 use crate::threader::workload::Workload;
@@ -12,6 +13,9 @@ pub struct Consumer {
     num_threads: u32,
     upstream: mpsc::Sender<ParallelSamples>,
     threads: Vec<thread::JoinHandle<()>>,
+    // stops the consumer thread, which should only happen after all of its workers stopped
+    done: Arc<RwLock<bool>>,
+    handle: Option<thread::JoinHandle<()>>,
 }
 
 impl Consumer {
@@ -21,10 +25,11 @@ impl Consumer {
         let threads = Vec::with_capacity(25);
         //println!("Started new consumer: {}", id);
 
-        thread::Builder::new()
+        let consumer_done = done.clone();
+        let handle = thread::Builder::new()
             .name(format!("consumer {}", id).to_string())
             .spawn(move || {
-                consumer(rx, downstream, done).unwrap();
+                consumer(rx, downstream, consumer_done).unwrap();
             })
             .unwrap();
         Consumer {
@@ -32,12 +37,35 @@ impl Consumer {
             num_threads: 0,
             upstream,
             threads,
+            done,
+            handle: Some(handle),
+        }
+    }
+    // The number of workers this consumer started
+    #[cfg(test)]
+    pub fn num_threads(&self) -> u32 {
+        self.num_threads
+    }
+    // Wait for all workers to stop (they should be signalled already), and then stop the consumer thread
+    pub fn finish(&mut self) {
+        for thread_handle in self.threads.drain(..) {
+            // a worker that panicked has stopped too, which is all we wait for
+            let _ = thread_handle.join();
+        }
+        if let Ok(mut done) = self.done.write() {
+            *done = true;
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
         }
     }
     pub fn scaleup(
         &mut self,
         mut extra_threads: u32,
         done: std::sync::Arc<std::sync::RwLock<bool>>,
+        connected: std::sync::Arc<std::sync::RwLock<u32>>,
+        paused: std::sync::Arc<std::sync::RwLock<bool>>,
+        refused: std::sync::Arc<std::sync::RwLock<u32>>,
         workload: Workload,
     ) -> u32 {
         let mut thread_handle: thread::JoinHandle<()>;
@@ -47,18 +75,44 @@ impl Consumer {
         }
         extra_threads -= leftover as u32;
         let start: u32 = self.id * THREADS_PER_CONSUMER as u32 + self.num_threads;
+        // None when pinning is not requested, or not supported on this platform
+        let core_ids = match workload.pin_cpus {
+            true => core_affinity::get_core_ids().filter(|ids| !ids.is_empty()),
+            false => None,
+        };
         let end: u32 = start + extra_threads;
 
         for thread_id in start..end {
             let workload: Workload = workload.clone();
             let upstream = self.upstream.clone();
             let thread_done = done.clone();
+            let thread_connected = connected.clone();
+            let thread_paused = paused.clone();
+            let thread_refused = refused.clone();
+            let core_id = core_ids
+                .as_ref()
+                .map(|ids| ids[thread_id as usize % ids.len()]);
             thread_handle = thread::Builder::new()
                 .name(format!("worker {}", thread_id).to_string())
                 .spawn(move || {
-                    Worker::new(thread_id, upstream, thread_done, workload)
-                        .procedure()
-                        .unwrap();
+                    if let Some(core_id) = core_id {
+                        // returns false where affinity is not supported, which we can live with
+                        core_affinity::set_for_current(core_id);
+                    }
+                    if let Err(err) = Worker::new(
+                        thread_id,
+                        upstream,
+                        thread_done,
+                        thread_connected,
+                        thread_paused,
+                        thread_refused,
+                        workload,
+                    )
+                    .procedure()
+                    {
+                        // the worker is not counted as connected, which is reported per step
+                        eprintln!("Error: worker {} stopped: {}", thread_id, err);
+                    }
                 })
                 .unwrap();
             self.threads.push(thread_handle);
@@ -69,15 +123,26 @@ impl Consumer {
     }
 }
 
+// Forward sooner when the channel runs dry, batch more while it keeps delivering.
+fn next_batch_size(batch_size: usize, idle: bool) -> usize {
+    let max_batch_size = (THREADS_PER_CONSUMER * SCALEDOWNFACTOR) as usize;
+    match idle {
+        true => (batch_size / 2).max(1),
+        false => (batch_size * 2).min(max_batch_size),
+    }
+}
+
 fn consumer(
     rx: mpsc::Receiver<ParallelSamples>,
     tx: mpsc::Sender<ParallelSamples>,
     done: Arc<RwLock<bool>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), PgTpsError> {
     //With more threads (> 500) we have some issues, where the one main thread cannot consume messages fast enough.
-    //This function can downscal from 25 messages to 1 message.
+    //This function can downscale many messages into 1 message.
+    //How many depends on how busy the channel is (see next_batch_size).
     let mut parallelsamples = ParallelSamples::new();
     let wait = std::time::Duration::from_millis(10);
+    let mut batch_size = THREADS_PER_CONSUMER as usize;
     loop {
         match done.read() {
             Ok(done) => {
@@ -87,19 +152,19 @@ fn consumer(
             }
             Err(_err) => (),
         };
-        for _ in 0..THREADS_PER_CONSUMER * SCALEDOWNFACTOR {
+        let mut idle = false;
+        for _ in 0..batch_size {
             match rx.recv_timeout(wait) {
                 Ok(sample) => {
                     parallelsamples = parallelsamples.append(&sample);
                 }
-                Err(_err) => (),
-                //                {
-                //                    if i%10 == 0 {
-                //                        println!("Timeout");
-                //                    }
-                //                }
+                Err(_err) => {
+                    idle = true;
+                    break;
+                }
             };
         }
+        batch_size = next_batch_size(batch_size, idle);
         if parallelsamples.len() > 0 {
             tx.send(parallelsamples)?;
             parallelsamples = ParallelSamples::new();
@@ -107,3 +172,22 @@ fn consumer(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_batch_size() {
+        let max_batch_size = (THREADS_PER_CONSUMER * SCALEDOWNFACTOR) as usize;
+        assert_eq!(next_batch_size(10, false), 20);
+        assert_eq!(next_batch_size(max_batch_size, false), max_batch_size);
+        assert_eq!(next_batch_size(10, true), 5);
+        assert_eq!(next_batch_size(1, true), 1);
+        let mut batch_size = 1;
+        for _ in 0..100 {
+            batch_size = next_batch_size(batch_size, false);
+        }
+        assert_eq!(batch_size, max_batch_size);
+    }
+}
@@ -1,53 +1,139 @@
+use crate::error::PgTpsError;
 use crate::threader::consumer::{Consumer, THREADS_PER_CONSUMER};
-use crate::threader::sample::{ParallelSamples, TestResult};
+use crate::threader::sample::{current_timeslice, ParallelSamples};
+pub use crate::threader::sample::{BatchStat, Totals};
 use crate::threader::workload::Workload;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use std::sync::{mpsc, Arc, RwLock};
 use std::thread;
 
+pub use crate::threader::sample::TestResult;
+pub use crate::threader::worker::INSERT_TABLE_NAME;
+
 mod consumer;
 mod sample;
 mod worker;
 pub mod workload;
 
+// How long a sub step of a ramp may take to connect, before the next one is started anyway (see max_ramp_rate)
+const RAMP_INTERVAL_MS: i64 = 1000;
+
 pub struct Threader {
     pub num_workers: usize,
+    // the maximum number of consumers, each running up to THREADS_PER_CONSUMER workers (see capacity)
     pub max_workers: usize,
+    // the maximum number of workers a scaleup starts at once, the rest follows in sub steps (0 means no maximum)
+    pub max_ramp_rate: u32,
+    // how often wait_stable logs the interim results (zero means never, see --heartbeat)
+    pub heartbeat: Duration,
+    // percentage of the highest and lowest timeslices left out when checking stability (see --trim-outliers)
+    pub trim_outliers: f64,
     //pub num_samples: u32,
     workload: Workload,
     tx: mpsc::Sender<ParallelSamples>,
     rx: mpsc::Receiver<ParallelSamples>,
     done: Arc<RwLock<bool>>,
+    connected: Arc<RwLock<u32>>,
+    paused: Arc<RwLock<bool>>,
+    refused: Arc<RwLock<u32>>,
+    // the number of workers, connected workers and refused workers before the last scaleup
+    scaleup_from: (u32, u32, u32),
+    // transactions, errors and deadlocks received since the last scaleup
+    step_totals: Totals,
+    // everything received since the last scaleup (the latest 100 timeslices, see --batch-stats)
+    step_samples: ParallelSamples,
+    measure_from: u32,
+    // when the last sample arrived, and how long we accept not receiving any (see --stall-timeout)
+    last_sample: DateTime<Utc>,
+    stall_timeout: Duration,
     consumers: Vec<Consumer>,
 }
 
 impl Threader {
-    pub fn new(mut max_workers: usize, workload: Workload) -> Threader {
+    pub fn new(mut max_workers: usize, workload: Workload, stall_timeout: Duration) -> Threader {
         if max_workers < 1 {
             max_workers = 1000
         }
         max_workers /= THREADS_PER_CONSUMER as usize;
         max_workers += 1;
         let done = Arc::new(RwLock::new(false));
+        let connected = Arc::new(RwLock::new(0));
+        let paused = Arc::new(RwLock::new(false));
+        let refused = Arc::new(RwLock::new(0));
         let (tx, rx) = mpsc::channel();
         let consumers = Vec::with_capacity(max_workers);
         Threader {
             workload,
             num_workers: 0,
             max_workers,
+            max_ramp_rate: 0,
+            heartbeat: Duration::zero(),
+            trim_outliers: 0.0,
             //num_samples: 0,
             tx,
             rx,
             done,
+            connected,
+            paused,
+            refused,
+            scaleup_from: (0, 0, 0),
+            step_totals: Totals::default(),
+            step_samples: ParallelSamples::new(),
+            measure_from: 0,
+            last_sample: Utc::now(),
+            stall_timeout,
             consumers,
         }
     }
-    pub fn scaleup(&mut self, new_workers: u32) {
+    // The number of workers this threader can run at most
+    pub fn capacity(&self) -> u32 {
+        (self.max_workers * THREADS_PER_CONSUMER as usize) as u32
+    }
+    pub fn scaleup(&mut self, new_workers: u32) -> Result<(), PgTpsError> {
+        if new_workers > self.capacity() {
+            return Err(PgTpsError::Config(format!(
+                "cannot scale up to {} clients, there are only {} worker slots ({} consumers of {} workers)",
+                new_workers,
+                self.capacity(),
+                self.max_workers,
+                THREADS_PER_CONSUMER
+            )));
+        }
+        self.scaleup_from = (self.num_workers as u32, self.connected(), self.refused());
+        self.step_totals = Totals::default();
+        self.step_samples = ParallelSamples::new();
+        if self.max_ramp_rate == 0 || new_workers - (self.num_workers as u32) <= self.max_ramp_rate
+        {
+            self.add_workers(new_workers);
+            return Ok(());
+        }
+        // a connection surge perturbs the measurement, so add at most max_ramp_rate workers at a time
+        while (self.num_workers as u32) < new_workers {
+            let sub_step = new_workers.min(self.num_workers as u32 + self.max_ramp_rate);
+            self.add_workers(sub_step);
+            let end_time = Utc::now() + Duration::milliseconds(RAMP_INTERVAL_MS);
+            while self.settled() < sub_step - self.scaleup_from.0 && Utc::now() < end_time {
+                thread::sleep(std::time::Duration::from_millis(10));
+            }
+        }
+        // everything sampled while ramping is left out of the measurement
+        self.measure_from = current_timeslice() + 1;
+        Ok(())
+    }
+    fn add_workers(&mut self, new_workers: u32) {
         let mut extra_workers = new_workers - self.num_workers as u32;
         //println!("New worker: {}, extra workers: {}", new_workers, extra_workers);
+        // consumers are only created when needed, so the last one may have room left (and before the first scaleup there is none),
+        // and whatever does not fit in it (all, when it is full) goes into new consumers
         if let Some(mut last_consumer) = self.consumers.pop() {
-            extra_workers =
-                last_consumer.scaleup(extra_workers, self.done.clone(), self.workload.clone());
+            extra_workers = last_consumer.scaleup(
+                extra_workers,
+                self.done.clone(),
+                self.connected.clone(),
+                self.paused.clone(),
+                self.refused.clone(),
+                self.workload.clone(),
+            );
             self.consumers.push(last_consumer);
         }
         for id in self.consumers.len()..self.max_workers {
@@ -55,44 +141,206 @@ impl Threader {
                 break;
             }
             let mut new_consumer = Consumer::new(id as u32, self.tx.clone());
-            extra_workers =
-                new_consumer.scaleup(extra_workers, self.done.clone(), self.workload.clone());
+            extra_workers = new_consumer.scaleup(
+                extra_workers,
+                self.done.clone(),
+                self.connected.clone(),
+                self.paused.clone(),
+                self.refused.clone(),
+                self.workload.clone(),
+            );
             self.consumers.push(new_consumer);
         }
         self.num_workers = new_workers as usize;
     }
-    pub fn finish(&self) {
-        if let Ok(mut done) = self.done.clone().write() {
+    // Wait until all workers are connected and initialized (or timeout has passed).
+    // Everything sampled before that moment is left out of the next wait_stable.
+    // Returns how long it took, or None on timeout.
+    pub fn wait_connected(&mut self, timeout: Duration) -> Option<Duration> {
+        let start = Utc::now();
+        let end_time = start + timeout;
+        let target = self.num_workers as u32;
+        let mut all_connected = false;
+        loop {
+            if let Ok(connected) = self.connected.read() {
+                all_connected = *connected >= target;
+            }
+            if all_connected || Utc::now() > end_time {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let duration = Utc::now() - start;
+        self.measure_from = current_timeslice() + 1;
+        match all_connected {
+            true => Some(duration),
+            false => None,
+        }
+    }
+    fn refused(&self) -> u32 {
+        match self.refused.read() {
+            Ok(refused) => *refused,
+            Err(_) => 0,
+        }
+    }
+    // The number of workers that connected or were refused since the last scaleup.
+    // Workers of earlier steps that stopped since (e.g. on a failed reconnect) are not part of it.
+    fn settled(&self) -> u32 {
+        let (_, from_connected, from_refused) = self.scaleup_from;
+        (self.connected() + self.refused()).saturating_sub(from_connected + from_refused)
+    }
+    // Wait until every worker of the last scaleup is either connected or refused (or timeout has passed).
+    // Returns how many of them did not connect, and how many were started.
+    pub fn refused_connections(&self, timeout: Duration) -> (u32, u32) {
+        let end_time = Utc::now() + timeout;
+        let (from_workers, _, from_refused) = self.scaleup_from;
+        let started = self.num_workers as u32 - from_workers;
+        while self.settled() < started && Utc::now() < end_time {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // workers that did not connect before timeout count as refused too
+        let pending = started.saturating_sub(self.settled());
+        (self.refused() - from_refused + pending, started)
+    }
+    // The number of transactions, errors and deadlocks since the last scaleup (see --find-limit)
+    pub fn step_totals(&self) -> Totals {
+        self.step_totals
+    }
+    // Requested and achieved transactions and duration of the samples per timeslice since the last scaleup
+    pub fn step_batches(&self) -> Vec<BatchStat> {
+        self.step_samples.batches()
+    }
+    // The number of workers that are connected and initialized right now
+    pub fn connected(&self) -> u32 {
+        match self.connected.read() {
+            Ok(connected) => *connected,
+            Err(_) => 0,
+        }
+    }
+    // Let all workers idle (pause) or continue (resume), as used for --burst-on and --burst-off
+    pub fn pause(&self) {
+        if let Ok(mut paused) = self.paused.write() {
+            *paused = true;
+        }
+    }
+    pub fn resume(&mut self) {
+        if let Ok(mut paused) = self.paused.write() {
+            *paused = false;
+        }
+        // anything still in the pipeline belongs to the previous burst
+        self.measure_from = current_timeslice() + 1;
+        // not receiving samples while paused is no reason to abort
+        self.last_sample = Utc::now();
+    }
+    // Watchdog: returns an error when no samples at all arrived for stall_timeout (zero disables it).
+    // This catches clients that hang without losing their connection, which never stabilize nor time out.
+    fn check_stalled(&self) -> Result<(), PgTpsError> {
+        if self.stall_timeout > Duration::zero()
+            && Utc::now() - self.last_sample > self.stall_timeout
+        {
+            return Err(PgTpsError::Interrupted(format!(
+                "no samples received for {}s, the server may be hung",
+                self.stall_timeout.num_seconds()
+            )));
+        }
+        Ok(())
+    }
+    // Collect samples for a fixed duration.
+    // Returns the mean of all complete timeslices, which is marked stable if it is within spread.
+    pub fn measure(
+        &mut self,
+        duration: Duration,
+        spread: f64,
+    ) -> Result<Option<TestResult>, PgTpsError> {
+        let start = Utc::now();
+        let end_time = start + duration;
+        let mut parallel_samples = ParallelSamples::new();
+        while Utc::now() < end_time {
+            let s = self.consume().since(self.measure_from);
+            // all of the duration counts, also when it is longer than 100 timeslices
+            parallel_samples = parallel_samples.extend(&s);
+            self.check_stalled()?;
+        }
+        let test_results = parallel_samples
+            .as_results(1, usize::MAX)
+            .trimmed(self.trim_outliers);
+        Ok(with_window(
+            test_results
+                .verify(spread)
+                .or_else(|| test_results.fallback(spread)),
+            start,
+        ))
+    }
+    // Collect samples until all workers together have run transactions (see --transactions-per-step).
+    // Returns the mean of all complete timeslices, with the time it took to run them as its window.
+    pub fn run_transactions(
+        &mut self,
+        transactions: u64,
+    ) -> Result<Option<TestResult>, PgTpsError> {
+        let start = Utc::now();
+        let mut parallel_samples = ParallelSamples::new();
+        let mut done: u64 = 0;
+        while done < transactions {
+            let s = self.consume().since(self.measure_from);
+            done += s.totals().transactions;
+            // all of the step counts, however long it takes
+            parallel_samples = parallel_samples.extend(&s);
+            self.check_stalled()?;
+        }
+        let end = Utc::now();
+        // the last timeslices are only complete when the samples of all workers arrived
+        parallel_samples = parallel_samples.extend(&self.consume());
+        let result = parallel_samples.as_results(1, usize::MAX).summary();
+        Ok(result.map(|mut result| {
+            // running them is all there is to it
+            result.stable = true;
+            result.window = Some((start, end));
+            result
+        }))
+    }
+    // Signal all workers to stop, and wait until all workers and consumers have stopped.
+    // Calling it again (e.g. from drop) does nothing, since every thread is joined only once.
+    pub fn finish(&mut self) {
+        if let Ok(mut done) = self.done.write() {
             *done = true;
         }
-
-        let wait = self.num_workers as u32 * std::time::Duration::from_millis(100) / 10;
-
-        thread::sleep(wait);
+        for consumer in self.consumers.iter_mut() {
+            consumer.finish();
+        }
     }
 
     pub fn wait_stable(
         &mut self,
         spread: f64,
         count: usize,
+        window: usize,
         max_wait: Duration,
-    ) -> Option<TestResult> {
-        let end_time = Utc::now() + max_wait;
+    ) -> Result<Option<TestResult>, PgTpsError> {
+        let start = Utc::now();
+        let end_time = start + max_wait;
         let mut parallel_samples = ParallelSamples::new();
         let mut i: usize = 0;
+        let mut next_heartbeat = start + self.heartbeat;
         loop {
-            let s = self.consume();
-            parallel_samples = parallel_samples.append(&s);
-            let test_results = parallel_samples.as_results(count, count + 1);
+            let s = self.consume().since(self.measure_from);
+            parallel_samples = parallel_samples.append(&s).window(count.max(window));
+            let test_results = parallel_samples
+                .as_results(count, window)
+                .trimmed(self.trim_outliers);
+            if !self.heartbeat.is_zero() && Utc::now() > next_heartbeat {
+                next_heartbeat = Utc::now() + self.heartbeat;
+                heartbeat(self.num_workers, start, test_results.summary());
+            }
             //            let stddev = test_result.std_deviation_absolute().unwrap();
             //            println!("tps: {}, latency: {}", stddev.tps, stddev.latency);
             if i > count && Utc::now() > end_time {
-                return test_results.mean();
+                return Ok(with_window(test_results.fallback(spread), start));
             }
             i += 1;
             if let Some(test_result) = test_results.verify(spread) {
-                return Some(test_result);
+                return Ok(with_window(Some(test_result), start));
             }
+            self.check_stalled()?;
         }
     }
 
@@ -110,7 +358,12 @@ impl Threader {
             match self.rx.recv_timeout(wait) {
                 Ok(pss) => {
                     //        println!("adding");
+                    self.step_totals.add(pss.totals());
+                    self.step_samples =
+                        std::mem::replace(&mut self.step_samples, ParallelSamples::new())
+                            .append(&pss);
                     parallel_samples = parallel_samples.append(&pss);
+                    self.last_sample = Utc::now();
                 }
                 Err(_err) => (),
             };
@@ -121,3 +374,197 @@ impl Threader {
         parallel_samples
     }
 }
+
+// Log the interim result of a step that is not stable yet, so that a slow step does not look like a hang
+fn heartbeat(num_workers: usize, start: DateTime<Utc>, result: Option<TestResult>) {
+    let elapsed = (Utc::now() - start).num_milliseconds() as f64 / 1000.0;
+    match result {
+        Some(result) => {
+            let (tps_stddev, latency_stddev) = result.stddev.unwrap_or((0.0, Duration::zero()));
+            eprintln!(
+                "Heartbeat: {} clients, waiting {:.1}s, tps {:.3} (stddev {:.3}), latency {}usec (stddev {}usec)",
+                num_workers,
+                elapsed,
+                result.tps,
+                tps_stddev,
+                result.latency.num_microseconds().unwrap_or(i64::MAX),
+                latency_stddev.num_microseconds().unwrap_or(i64::MAX)
+            )
+        }
+        None => eprintln!(
+            "Heartbeat: {} clients, waiting {:.1}s, no samples yet",
+            num_workers, elapsed
+        ),
+    }
+}
+
+// A result with the window it was collected in, from start until now
+fn with_window(result: Option<TestResult>, start: DateTime<Utc>) -> Option<TestResult> {
+    result.map(|mut result| {
+        result.window = Some((start, Utc::now()));
+        result
+    })
+}
+
+// Also stop all threads when we return early (e.g. on an error) without calling finish
+impl Drop for Threader {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsn::Dsn;
+    use crate::generic;
+
+    #[test]
+    fn test_drop_stops_threads() {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // the workers cannot connect, but the consumers keep running until done
+            let dsn = Dsn::from_string("host=/nonexistent port=1");
+            let mut threader = Threader::new(
+                10,
+                Workload::new(dsn, "".to_string(), false, false),
+                Duration::zero(),
+            );
+            threader.scaleup(3).unwrap();
+            let done = threader.done.clone();
+            // returns once all workers and consumers have stopped
+            drop(threader);
+            tx.send(*done.read().unwrap()).unwrap();
+        });
+        assert!(rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap());
+    }
+
+    #[test]
+    fn test_refused_connections() {
+        let dsn = Dsn::from_string("host=/nonexistent port=1");
+        let mut threader = Threader::new(
+            10,
+            Workload::new(dsn, "".to_string(), false, false),
+            Duration::zero(),
+        );
+        threader.scaleup(2).unwrap();
+        assert_eq!(threader.refused_connections(Duration::seconds(10)), (2, 2));
+        threader.scaleup(5).unwrap();
+        assert_eq!(threader.refused_connections(Duration::seconds(10)), (3, 3));
+    }
+
+    #[test]
+    fn test_refused_connections_stopped_worker() {
+        let dsn = Dsn::from_string("host=/nonexistent port=1");
+        let mut threader = Threader::new(
+            10,
+            Workload::new(dsn, "".to_string(), false, false),
+            Duration::zero(),
+        );
+        threader.scaleup(2).unwrap();
+        assert_eq!(threader.refused_connections(Duration::seconds(10)), (2, 2));
+        // as if one of them had connected instead, and stopped since (e.g. on a failed reconnect),
+        // which leaves a worker that is neither connected nor refused
+        *threader.refused.write().unwrap() -= 1;
+        threader.scaleup(5).unwrap();
+        let start = Utc::now();
+        assert_eq!(threader.refused_connections(Duration::seconds(10)), (3, 3));
+        assert!(Utc::now() - start < Duration::seconds(10));
+    }
+
+    #[test]
+    fn test_max_ramp_rate() {
+        let dsn = Dsn::from_string("host=/nonexistent port=1");
+        let mut threader = Threader::new(
+            10,
+            Workload::new(dsn, "".to_string(), false, false),
+            Duration::zero(),
+        );
+        threader.max_ramp_rate = 2;
+        // in sub steps of 2, 2 and 1 workers, which all count as one step
+        threader.scaleup(5).unwrap();
+        assert_eq!(threader.num_workers, 5);
+        assert_eq!(threader.refused_connections(Duration::seconds(10)), (5, 5));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let dsn = Dsn::from_string("host=/nonexistent port=1");
+        // 5 workers need a single consumer, with room for THREADS_PER_CONSUMER workers
+        let mut threader = Threader::new(
+            5,
+            Workload::new(dsn, "".to_string(), false, false),
+            Duration::zero(),
+        );
+        assert_eq!(threader.capacity(), 10);
+        threader.scaleup(10).unwrap();
+        // more clients than there are worker slots is an error, rather than silently less clients
+        assert!(matches!(threader.scaleup(11), Err(PgTpsError::Config(_))));
+        assert_eq!(threader.num_workers, 10);
+    }
+
+    #[test]
+    fn test_scaleup_sequences() {
+        let dsn = Dsn::from_string("host=/nonexistent port=1");
+        for sequence in [
+            vec![1, 2, 3, 5, 8, 13, 21, 34],
+            vec![10, 20, 30],
+            vec![9, 10, 11, 20],
+            vec![5, 10, 15, 40],
+            vec![40],
+        ] {
+            let mut threader = Threader::new(
+                40,
+                Workload::new(dsn.clone(), "".to_string(), false, false),
+                Duration::zero(),
+            );
+            for target in sequence.iter() {
+                threader.scaleup(*target).unwrap();
+                let spawned: Vec<u32> = threader
+                    .consumers
+                    .iter()
+                    .map(|consumer| consumer.num_threads())
+                    .collect();
+                assert_eq!(
+                    spawned.iter().sum::<u32>(),
+                    *target,
+                    "{:?}: {:?}",
+                    sequence,
+                    spawned
+                );
+                // consumers are filled up one at a time, so the worker ids are 0 up to target
+                let (last, full) = spawned.split_last().unwrap();
+                assert!(full.iter().all(|n| *n == THREADS_PER_CONSUMER as u32));
+                assert!((1..=THREADS_PER_CONSUMER as u32).contains(last));
+            }
+            threader.finish();
+        }
+    }
+
+    // End to end: workers, consumers and threader against a real server (set TEST_CONNSTR)
+    #[test]
+    #[ignore]
+    fn test_threader_smoke() -> Result<(), PgTpsError> {
+        let constr = generic::get_env_str("", "TEST_CONNSTR", "").to_string();
+        if constr.is_empty() {
+            return Ok(());
+        }
+        let dsn = Dsn::from_string(constr.as_str());
+        let mut threader = Threader::new(
+            2,
+            Workload::new(dsn, "".to_string(), true, true),
+            Duration::seconds(10),
+        );
+        threader.scaleup(2)?;
+        assert!(threader.wait_connected(Duration::seconds(10)).is_some());
+        let result = threader
+            .wait_stable(100.0, 3, 4, Duration::seconds(10))?
+            .expect("a result");
+        threader.finish();
+        assert!(result.stable);
+        assert!(result.tps > 0.0);
+        let (start, end) = result.window.expect("a window");
+        assert!(start < end);
+        Ok(())
+    }
+}
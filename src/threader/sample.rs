@@ -40,24 +40,55 @@ use num::integer::Roots;
 // for 100msec and keeping track of results
 pub struct Sample {
     transactions: u64,
+    rollbacks: u64,
+    // rollbacks because the server detected a deadlock (see --shuffle)
+    deadlocks: u64,
+    // the number of transactions the worker meant to run (see --batch-stats)
+    requested: u64,
     wait: Duration,
+    overhead: Duration,
+    // the first transaction of a session, which is part of transactions and wait too (see --cold-latency)
+    cold: u64,
+    cold_wait: Duration,
+    // rows affected by the statements (see --count-rows)
+    rows: u64,
+    // the transaction that was tagged with a trace id (see --exemplars)
+    trace: Option<Trace>,
     start: DateTime<Utc>,
     end: DateTime<Utc>,
 }
 
+// The current and the previous timeslice may still receive samples, so they are not part of any result
+const INCOMPLETE_TIMESLICES: usize = 2;
+
+// A transaction that was tagged with a trace id, and its latency (see --exemplars)
+pub type Trace = (u128, Duration);
+
+// Of two traced transactions the slowest is the most interesting one to drill into
+fn slowest(trace: Option<Trace>, other: Option<Trace>) -> Option<Trace> {
+    match (trace, other) {
+        (Some(trace), Some(other)) if other.1 > trace.1 => Some(other),
+        (None, other) => other,
+        (trace, _) => trace,
+    }
+}
+
 fn timeslice(when: DateTime<Utc>) -> u32 {
     ((when - Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap()).num_milliseconds() / 200) as u32
 }
 
-fn current_timeslice() -> u32 {
+pub fn current_timeslice() -> u32 {
     timeslice(chrono::Utc::now())
 }
 
-fn percent_of(first: f64, second: f64) -> f64 {
-    if first == 0.0 {
-        return 0.0;
+// The standard deviation as a percentage of the mean (the coefficient of variation),
+// or None when the mean is not positive (e.g. a failing workload without transactions or waits),
+// which is degenerate rather than perfectly stable
+fn coefficient_of_variation(mean: f64, stddev: f64) -> Option<f64> {
+    match mean > 0.0 {
+        true => Some(100.0 * stddev / mean),
+        false => None,
     }
-    100.0 * second / first
 }
 
 /* Duration supports a div by i32, but sometimes we have an overflow if we use that option*/
@@ -67,7 +98,7 @@ fn div_duration(d: Duration, x: u64) -> Duration {
         Duration::zero()
     } else if x > MAX_U32 {
         match i32::try_from(x.sqrt()).ok() {
-            Some(rt) => return d / rt,
+            Some(rt) => d / rt,
             None => panic!("that is some unexpected divisor: {}", x),
         }
     } else {
@@ -88,25 +119,87 @@ impl Sample {
     pub fn new() -> Sample {
         Sample {
             transactions: 0,
+            rollbacks: 0,
+            deadlocks: 0,
+            requested: 0,
             wait: Duration::zero(),
+            overhead: Duration::zero(),
+            cold: 0,
+            cold_wait: Duration::zero(),
+            rows: 0,
+            trace: None,
             start: chrono::Utc::now(),
             end: chrono::Utc::now(),
         }
     }
-    // add a transaction (with the duration of it)
-    pub fn increment(&mut self, wait: Duration) {
+    // add a transaction (with the duration of it, and the part of that spent on begin and commit)
+    pub fn increment(&mut self, wait: Duration, overhead: Duration) {
         self.transactions += 1;
-        self.wait = self.wait + wait;
+        self.wait += wait;
+        self.overhead += overhead;
+    }
+    // mark the last transaction as the first of a session (with the same wait it was incremented with)
+    pub fn cold(&mut self, wait: Duration) {
+        self.cold += 1;
+        self.cold_wait += wait;
+    }
+    // mark the last transaction as tagged with trace_id (with the same wait it was incremented with)
+    pub fn traced(&mut self, trace_id: u128, wait: Duration) {
+        self.trace = slowest(self.trace, Some((trace_id, wait)));
+    }
+    // add the rows affected by a transaction
+    pub fn affected(&mut self, rows: u64) {
+        self.rows += rows;
+    }
+    // mark the last transaction as rolled back (see --rollback-ratio)
+    pub fn rolled_back(&mut self) {
+        self.rollbacks += 1;
+    }
+    // the number of transactions the worker meant to run in this sample
+    pub fn requested(&mut self, requested: u64) {
+        self.requested = requested;
+    }
+    // mark the last transaction as rolled back by a deadlock
+    pub fn deadlocked(&mut self) {
+        self.rollbacks += 1;
+        self.deadlocks += 1;
     }
     // stop sampling
     pub fn end(&mut self) {
         self.end = chrono::Utc::now();
         //println!("{}", (self.end-self.start).num_microseconds().unwrap_or(0));
     }
+    // how many transactions are in this sample
+    pub fn transactions(self) -> u64 {
+        self.transactions
+    }
+    // a copy of this sample that only reports elapsed time, without transactions or waits
+    pub fn without_transactions(self) -> Sample {
+        Sample {
+            transactions: 0,
+            rollbacks: 0,
+            deadlocks: 0,
+            requested: 0,
+            wait: Duration::zero(),
+            overhead: Duration::zero(),
+            cold: 0,
+            cold_wait: Duration::zero(),
+            rows: 0,
+            trace: None,
+            start: self.start,
+            end: self.end,
+        }
+    }
     // how many transactions did we process per second
+    // (0 when no time has passed, which a coarse clock can report, and saturating when a lot has)
     pub fn tps(self) -> f64 {
-        let duration: f64 = (self.end - self.start).num_nanoseconds().unwrap() as f64;
-        1e9_f64 * (self.transactions as f64) / duration
+        let duration = (self.end - self.start)
+            .num_nanoseconds()
+            .unwrap_or(i64::MAX);
+        if duration <= 0 {
+            return 0.0;
+        }
+        1e9_f64 * (self.transactions as f64) / duration as f64
     }
     /*
     // how many seconds did we waited for a transaction to return
@@ -125,20 +218,43 @@ impl Sample {
         ParallelSample {
             timeslice: timeslice(self.start),
             total_transactions: self.transactions,
+            total_rollbacks: self.rollbacks,
+            total_deadlocks: self.deadlocks,
+            total_requested: self.requested,
+            total_errors: 0,
             total_waits: self.wait,
+            total_overhead: self.overhead,
+            total_cold: self.cold,
+            total_cold_waits: self.cold_wait,
+            total_rows: self.rows,
+            trace: self.trace,
             total_duration: self.end - self.start,
             num_samples: 1,
         }
     }
 }
 
+// A timeslice, with the requested and achieved transactions and the duration (msec) of a sample
+pub type BatchStat = (u32, f64, f64, f64);
+
 // ParallelSample are meant as a set of multiple samples within the same period
 // run on multiple threads. For efficiency it has a totally different memory structure,
 // which only has the summaries data from all added samples.
 pub struct ParallelSample {
     pub timeslice: u32,
     total_transactions: u64,
+    total_rollbacks: u64,
+    total_deadlocks: u64,
+    total_requested: u64,
+    // failed samples (see error_sample), which have no transactions and do not count as a sample
+    total_errors: u64,
     total_waits: Duration,
+    total_overhead: Duration,
+    total_cold: u64,
+    total_cold_waits: Duration,
+    total_rows: u64,
+    // the slowest traced transaction (see --exemplars)
+    trace: Option<Trace>,
     total_duration: Duration,
     pub num_samples: u64,
 }
@@ -152,10 +268,33 @@ impl Clone for ParallelSample {
 }
 
 impl ParallelSample {
+    // A worker that fails to run a sample (e.g. a query error) reports that as an error sample
+    pub fn error_sample() -> ParallelSample {
+        ParallelSample {
+            timeslice: current_timeslice(),
+            total_transactions: 0,
+            total_rollbacks: 0,
+            total_deadlocks: 0,
+            total_requested: 0,
+            total_errors: 1,
+            total_waits: Duration::zero(),
+            total_overhead: Duration::zero(),
+            total_cold: 0,
+            total_cold_waits: Duration::zero(),
+            total_rows: 0,
+            trace: None,
+            total_duration: Duration::zero(),
+            num_samples: 0,
+        }
+    }
     // avg latency is the average amount of waits over all samples contained
     pub fn avg_latency(&self) -> Duration {
         div_duration(self.total_waits, self.total_transactions)
     }
+    // avg overhead is the average part of the latency that was spent on begin and commit
+    pub fn avg_overhead(&self) -> Duration {
+        div_duration(self.total_overhead, self.total_transactions)
+    }
     /*
     // initialize a new without data
     pub fn new(timeslice: u32) -> ParallelSample {
@@ -174,8 +313,17 @@ impl ParallelSample {
             return Err("trying to combine samples of different timeslices");
         }
         self.total_transactions += samples.total_transactions;
-        self.total_waits = self.total_waits + samples.total_waits;
-        self.total_duration = self.total_duration + samples.total_duration;
+        self.total_rollbacks += samples.total_rollbacks;
+        self.total_errors += samples.total_errors;
+        self.total_deadlocks += samples.total_deadlocks;
+        self.total_requested += samples.total_requested;
+        self.total_waits += samples.total_waits;
+        self.total_overhead += samples.total_overhead;
+        self.total_cold += samples.total_cold;
+        self.total_cold_waits += samples.total_cold_waits;
+        self.total_rows += samples.total_rows;
+        self.trace = slowest(self.trace, samples.trace);
+        self.total_duration += samples.total_duration;
         self.num_samples += samples.num_samples;
         Ok(())
     }
@@ -188,19 +336,74 @@ impl ParallelSample {
         }
         let duration_ns: f64 = div_duration(self.total_duration, self.num_samples)
             .num_nanoseconds()
-            .unwrap() as f64;
+            .unwrap_or(i64::MAX) as f64;
         match duration_ns < 1_f64 {
             true => 0_f64,
             false => 1e9_f64 * (self.total_transactions as f64) / duration_ns,
         }
     }
+    // the fraction of transactions that was rolled back
+    pub fn rollback_ratio(&self) -> f64 {
+        match self.total_transactions {
+            0 => 0.0,
+            transactions => self.total_rollbacks as f64 / transactions as f64,
+        }
+    }
     pub fn as_testresult(&self) -> TestResult {
         TestResult {
             stable: false,
             tps: self.tot_tps(),
             latency: self.avg_latency(),
+            overhead: self.avg_overhead(),
+            rollback_ratio: self.rollback_ratio(),
+            samples: 1,
+            stddev: None,
+            window: None,
+            trace: self.trace,
+            confidence: None,
+        }
+    }
+}
+
+// Counts over a number of samples, e.g. everything since a scaleup
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Totals {
+    pub transactions: u64,
+    pub errors: u64,
+    pub deadlocks: u64,
+    pub waits: Duration,
+    // the first transactions of every session, which are part of transactions and waits too
+    pub cold: u64,
+    pub cold_waits: Duration,
+    // rows affected by all transactions (see --count-rows)
+    pub rows: u64,
+}
+
+impl Totals {
+    pub fn add(&mut self, other: Totals) {
+        self.transactions += other.transactions;
+        self.errors += other.errors;
+        self.deadlocks += other.deadlocks;
+        self.waits += other.waits;
+        self.cold += other.cold;
+        self.cold_waits += other.cold_waits;
+        self.rows += other.rows;
+    }
+    // the average number of rows affected per transaction
+    pub fn rows_per_transaction(&self) -> f64 {
+        match self.transactions {
+            0 => 0.0,
+            transactions => self.rows as f64 / transactions as f64,
         }
     }
+    // the average latency of the first transaction of a session
+    pub fn cold_latency(&self) -> Duration {
+        div_duration(self.cold_waits, self.cold)
+    }
+    // the average latency of all other transactions
+    pub fn warm_latency(&self) -> Duration {
+        div_duration(self.waits - self.cold_waits, self.transactions - self.cold)
+    }
 }
 
 pub struct ParallelSamples {
@@ -261,20 +464,67 @@ impl ParallelSamples {
     pub fn len(&self) -> usize {
         self.parallel_samples.len()
     }
+    // Per timeslice: the requested and achieved transactions and the duration (msec) of a sample (on average)
+    pub fn batches(&self) -> Vec<BatchStat> {
+        self.parallel_samples
+            .values()
+            .filter(|ps| ps.num_samples > 0)
+            .map(|ps| {
+                let samples = ps.num_samples as f64;
+                (
+                    ps.timeslice,
+                    ps.total_requested as f64 / samples,
+                    ps.total_transactions as f64 / samples,
+                    ps.total_duration.num_microseconds().unwrap_or_default() as f64
+                        / 1000.0
+                        / samples,
+                )
+            })
+            .collect()
+    }
+    // The number of transactions, errors and deadlocks over all timeslices
+    pub fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for ps in self.parallel_samples.values() {
+            totals.transactions += ps.total_transactions;
+            totals.errors += ps.total_errors;
+            totals.deadlocks += ps.total_deadlocks;
+            totals.waits += ps.total_waits;
+            totals.cold += ps.total_cold;
+            totals.cold_waits += ps.total_cold_waits;
+            totals.rows += ps.total_rows;
+        }
+        totals
+    }
     pub fn limit(mut self, limit: usize) -> ParallelSamples {
         for _ in limit..self.len() {
             self.parallel_samples.pop_first();
         }
         self
     }
-    pub fn append(mut self, samples: &ParallelSamples) -> ParallelSamples {
+    // Only keep what an analysis of the latest `size` timeslices needs (see as_results),
+    // so that waiting for a step to stabilize does not take more memory the longer it takes
+    pub fn window(self, size: usize) -> ParallelSamples {
+        self.limit(size + INCOMPLETE_TIMESLICES)
+    }
+    // drop all timeslices before `from`
+    pub fn since(mut self, from: u32) -> ParallelSamples {
+        self.parallel_samples
+            .retain(|timeslice, _| *timeslice >= from);
+        self
+    }
+    pub fn append(self, samples: &ParallelSamples) -> ParallelSamples {
+        self.extend(samples).limit(100)
+    }
+    // Like append, but keeps every timeslice, for results over all of a step (e.g. --transactions-per-step)
+    pub fn extend(mut self, samples: &ParallelSamples) -> ParallelSamples {
         for sample in samples.parallel_samples.values() {
             self.add(*sample);
         }
-        self.limit(100)
+        self
     }
     pub fn as_results(&self, min: usize, max: usize) -> TestResults {
-        let previous_timeslice = current_timeslice() - 1;
+        let previous_timeslice = current_timeslice() + 1 - INCOMPLETE_TIMESLICES as u32;
         let mut results = TestResults::new(min, max);
         for (_, parallel_sample) in self.parallel_samples.clone() {
             if parallel_sample.timeslice >= previous_timeslice {
@@ -290,6 +540,20 @@ pub struct TestResult {
     pub stable: bool,
     pub tps: f64,
     pub latency: Duration,
+    // the part of latency spent on begin and commit (zero for non transactional workloads)
+    pub overhead: Duration,
+    // the fraction of transactions that was rolled back instead of committed
+    pub rollback_ratio: f64,
+    // the number of timeslices this result is based on
+    pub samples: usize,
+    // the standard deviation of tps and latency over those timeslices (for a mean of them)
+    pub stddev: Option<(f64, Duration)>,
+    // when collecting started and ended (see Threader::wait_stable), to align with external monitoring
+    pub window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    // the slowest transaction that was tagged with a trace id (see --exemplars)
+    pub trace: Option<Trace>,
+    // how much the result can be trusted (0-100), when it was checked against a spread (see TestResults::confidence)
+    pub confidence: Option<f64>,
 }
 
 impl Copy for TestResult {}
@@ -300,9 +564,26 @@ impl Clone for TestResult {
     }
 }
 pub struct TestResults {
+    // verify only checks the spread after this many results were appended
     pub min: usize,
+    // the sliding window: only the latest max results are kept
     max: usize,
     results: Vec<TestResult>,
+    appended: usize,
+    // percentage of the highest and of the lowest tps and latency left out of mean and stddev (see --trim-outliers)
+    trim: f64,
+}
+
+// A sorted copy of values without the top and bottom trim percent of them, but never without all of them
+fn trim_sorted<T: Copy + PartialOrd>(values: impl Iterator<Item = T>, trim: f64) -> Vec<T> {
+    let mut values: Vec<T> = values.collect();
+    if trim <= 0.0 {
+        return values;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let cut =
+        ((values.len() as f64 * trim / 100.0) as usize).min(values.len().saturating_sub(1) / 2);
+    values[cut..values.len() - cut].to_vec()
 }
 
 impl TestResults {
@@ -311,10 +592,24 @@ impl TestResults {
             min,
             max,
             results: Vec::new(),
+            appended: 0,
+            trim: 0.0,
         }
     }
+    // Leave the top and bottom trim percent of the tps and latency of the timeslices out of mean and stddev,
+    // so that a single spike (e.g. a checkpoint) does not keep a step from stabilizing (see --trim-outliers)
+    pub fn trimmed(mut self, trim: f64) -> TestResults {
+        self.trim = trim;
+        self
+    }
+    fn tps_values(&self) -> Vec<f64> {
+        trim_sorted(self.results.iter().map(|tr| tr.tps), self.trim)
+    }
+    fn latency_values(&self) -> Vec<Duration> {
+        trim_sorted(self.results.iter().map(|tr| tr.latency), self.trim)
+    }
     fn tot_tps(&self) -> f64 {
-        self.results.iter().map(|tr| tr.tps).sum::<f64>()
+        self.tps_values().iter().sum::<f64>()
     }
     fn avg_latency(&self) -> Duration {
         // I wished I could do something like this instead:
@@ -322,8 +617,8 @@ impl TestResults {
         // But I get `the trait bound `chrono::Duration: Sum` is not satisfied`
         let mut num: u64 = 0;
         let mut tot_lat = Duration::zero();
-        for tr in self.results.clone() {
-            tot_lat = tot_lat + tr.latency;
+        for latency in self.latency_values() {
+            tot_lat += latency;
             num += 1
         }
         match num {
@@ -331,52 +626,92 @@ impl TestResults {
             _ => div_duration(tot_lat, num),
         }
     }
+    fn avg_overhead(&self) -> Duration {
+        let mut tot_overhead = Duration::zero();
+        for tr in self.results.iter() {
+            tot_overhead += tr.overhead;
+        }
+        div_duration(tot_overhead, self.results.len() as u64)
+    }
+    fn avg_rollback_ratio(&self) -> f64 {
+        match self.results.len() {
+            0 => 0.0,
+            count => self.results.iter().map(|tr| tr.rollback_ratio).sum::<f64>() / count as f64,
+        }
+    }
     fn len(&self) -> usize {
         self.results.len()
     }
     pub fn mean(&self) -> Option<TestResult> {
         let sum_tps = self.tot_tps();
         let avg_latency = self.avg_latency();
+        let avg_overhead = self.avg_overhead();
         let count = self.len();
 
         match count {
             positive if positive > 0 => Some(TestResult {
                 stable: false,
-                tps: sum_tps / (count as f64),
+                tps: sum_tps / (self.tps_values().len() as f64),
                 latency: avg_latency,
+                overhead: avg_overhead,
+                rollback_ratio: self.avg_rollback_ratio(),
+                samples: count,
+                stddev: None,
+                window: None,
+                trace: self
+                    .results
+                    .iter()
+                    .fold(None, |trace, tr| slowest(trace, tr.trace)),
+                confidence: None,
             }),
             _ => None,
         }
     }
 
+    // The mean, with the standard deviation it has
+    pub fn summary(&self) -> Option<TestResult> {
+        self.mean().map(|mut mean| {
+            mean.stddev = self
+                .std_deviation_absolute()
+                .map(|stddev| (stddev.tps, stddev.latency));
+            mean
+        })
+    }
+
     pub fn std_deviation_absolute(&self) -> Option<TestResult> {
         match (self.mean(), self.results.len()) {
             (Some(results), count) if count > 0 => {
-                let tps_variance = self
-                    .results
+                let tps_values = self.tps_values();
+                let tps_variance = tps_values
                     .iter()
-                    .map(|tr| {
-                        let tps_diff = results.tps - tr.tps;
+                    .map(|tps| {
+                        let tps_diff = results.tps - tps;
                         tps_diff * tps_diff
                     })
                     .sum::<f64>()
-                    / count as f64;
-                let lat_variance = self
-                    .results
+                    / tps_values.len() as f64;
+                let latency_values = self.latency_values();
+                let lat_variance = latency_values
                     .iter()
-                    .map(|tr| {
-                        let lat_diff = (results.latency - tr.latency)
-                            .num_microseconds()
-                            .unwrap_or(0) as f64;
+                    .map(|latency| {
+                        let lat_diff =
+                            (results.latency - *latency).num_microseconds().unwrap_or(0) as f64;
                         lat_diff * lat_diff
                     })
                     .sum::<f64>()
-                    / count as f64;
+                    / latency_values.len() as f64;
 
                 Some(TestResult {
                     stable: false,
                     tps: tps_variance.sqrt(),
                     latency: Duration::microseconds(lat_variance.sqrt() as i64),
+                    overhead: Duration::zero(),
+                    rollback_ratio: 0.0,
+                    samples: count,
+                    stddev: None,
+                    window: None,
+                    trace: None,
+                    confidence: None,
                 })
             }
             _ => None,
@@ -389,25 +724,61 @@ impl TestResults {
     */
     pub fn append(&mut self, result: TestResult) {
         self.results.insert(self.results.len(), result);
+        self.appended += 1;
         if self.results.len() > self.max {
             self.results.remove(0);
         }
     }
+    // The confidence (0-100) in result, as documented in the README:
+    // 100 * (1 - cv / (2 * spread)) * min(1, samples / min), halved when the result did not stabilize,
+    // where cv is the highest coefficient of variation (stddev as a percentage of the mean) of tps and latency.
+    // So a stable result scores between 50 (right at spread) and 100 (no variation at all) when it has enough samples.
+    pub fn confidence(&self, result: &TestResult, spread: f64) -> f64 {
+        let micros = |latency: Duration| latency.num_microseconds().unwrap_or(0) as f64;
+        let cv = result.stddev.and_then(|(tps, latency)| {
+            let tps = coefficient_of_variation(result.tps, tps)?;
+            let latency = coefficient_of_variation(micros(result.latency), micros(latency))?;
+            Some(tps.max(latency))
+        });
+        let margin = match cv {
+            Some(cv) if spread > 0.0 => (1.0 - cv / (2.0 * spread)).clamp(0.0, 1.0),
+            // a degenerate result (e.g. without transactions) cannot be trusted
+            _ => 0.0,
+        };
+        let coverage = (result.samples as f64 / self.min.max(1) as f64).min(1.0);
+        let stabilized = match result.stable {
+            true => 1.0,
+            false => 0.5,
+        };
+        100.0 * margin * coverage * stabilized
+    }
+    // The summary of a step that did not stabilize (e.g. at max-wait), with its confidence against spread
+    pub fn fallback(&self, spread: f64) -> Option<TestResult> {
+        self.summary().map(|mut summary| {
+            summary.confidence = Some(self.confidence(&summary, spread));
+            summary
+        })
+    }
     pub fn verify(&self, spread: f64) -> Option<TestResult> {
-        if self.results.len() < self.min {
+        if self.appended < self.min {
             return None;
         }
+        let within = |mean: f64, stddev: f64| {
+            coefficient_of_variation(mean, stddev).is_some_and(|cv| (0.0..spread).contains(&cv))
+        };
         match (self.std_deviation_absolute(), self.mean()) {
             (Some(stdev), Some(mut mean)) => {
-                if !((0.0..spread).contains(&percent_of(mean.tps, stdev.tps))
-                    && (0.0..spread).contains(&percent_of(
+                if !(within(mean.tps, stdev.tps)
+                    && within(
                         mean.latency.num_microseconds().unwrap_or(0) as f64,
                         stdev.latency.num_microseconds().unwrap_or(0) as f64,
-                    )))
+                    ))
                 {
                     None
                 } else {
                     mean.stable = true;
+                    mean.stddev = Some((stdev.tps, stdev.latency));
+                    mean.confidence = Some(self.confidence(&mean, spread));
                     Some(mean)
                 }
             }
@@ -432,14 +803,14 @@ mod tests {
             if self.len() == 0 {
                 return 0.0;
             }
-            self.tot_tps() / (self.len() as f64)
+            self.tot_tps() / (self.tps_values().len() as f64)
         }
     }
 
     fn create_test_sample(num_transaction: usize, wait: Duration) -> Sample {
         let mut sample = Sample::new();
         for _ in 1..num_transaction {
-            sample.increment(wait);
+            sample.increment(wait, wait / 5);
         }
         thread::sleep(time::Duration::from_millis(200));
         sample.end();
@@ -462,7 +833,7 @@ mod tests {
         for slice in from_ts..(from_ts + num_ts as u32) {
             ps.timeslice = slice;
             ps.num_samples += increase;
-            pps.add(ps.clone());
+            pps.add(ps);
         }
         pps
     }
@@ -470,7 +841,7 @@ mod tests {
         if first == 0.0 {
             return 0.0;
         }
-        return 100.0 * second / first;
+        100.0 * second / first
     }
     #[test]
     fn test_percent_of() {
@@ -484,18 +855,41 @@ mod tests {
     #[test]
     fn test_sample() {
         let sample = create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS));
-        let s_tps = sample.clone().tps();
+        let s_tps = sample.tps();
         assert!(s_tps < 180_f64);
 
         let ms = sample.to_parallel_sample();
         assert_eq!(s_tps, ms.tot_tps());
         assert_eq!(ms.avg_latency().num_microseconds().unwrap(), 5000);
+        assert_eq!(ms.avg_overhead().num_microseconds().unwrap(), 1000);
+        assert_eq!(
+            ms.as_testresult().overhead.num_microseconds().unwrap(),
+            1000
+        );
+        assert_eq!(ms.rollback_ratio(), 0.0);
+        let mut rolled_back = sample;
+        for _ in 0..sample.transactions() / 4 {
+            rolled_back.rolled_back();
+        }
+        let ratio = rolled_back.to_parallel_sample().rollback_ratio();
+        assert!(ratio > 0.2 && ratio <= 0.25);
+    }
+    #[test]
+    fn test_sample_without_duration() {
+        let mut sample = Sample::new();
+        sample.increment(Duration::zero(), Duration::zero());
+        sample.end = sample.start;
+        assert_eq!(sample.tps(), 0.0);
+        assert_eq!(sample.to_parallel_sample().tot_tps(), 0.0);
+        // a duration that does not fit in nanoseconds saturates instead of panicking
+        sample.end = sample.start + Duration::days(365 * 1000);
+        assert!(sample.tps() >= 0.0);
     }
     #[test]
     fn test_parallel_sample() {
         let sample = create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS));
         let ps = create_test_parasample(sample, NUM_THREADS);
-        let mut other = ps.clone();
+        let mut other = ps;
         other.timeslice += 1;
         assert_eq!(
             other.add(ps).unwrap_err(),
@@ -515,7 +909,7 @@ mod tests {
         let ps = create_test_parasample(sample, NUM_THREADS);
         let mut pss = ParallelSamples::new();
         pss.add(ps);
-        let mut other = ps.clone();
+        let mut other = ps;
         let mut other_pss = ParallelSamples::new();
         other_pss.add(other);
         for i in 1..101 {
@@ -532,6 +926,126 @@ mod tests {
         assert_eq!(pss.count(), 5);
     }
     #[test]
+    fn test_parallel_samples_since() {
+        let sample = create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS));
+        let ps = create_test_parasample(sample, NUM_THREADS);
+        let mut pss = create_test_parasamples(ps, 100, 5, 0);
+        pss = pss.since(102);
+        assert_eq!(pss.len(), 3);
+        assert_eq!(pss.next().unwrap().timeslice, 102);
+        pss = pss.since(200);
+        assert_eq!(pss.len(), 0);
+    }
+    #[test]
+    fn test_parallel_samples_window() {
+        let sample = create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS));
+        let mut ps = create_test_parasample(sample, NUM_THREADS);
+        // a long wait (1000 timeslices of which the latest 10 are analyzed) keeps no more than it needs
+        ps.timeslice = current_timeslice() - 1000;
+        let mut pss = ParallelSamples::new();
+        for _ in 0..1000 {
+            let mut other_pss = ParallelSamples::new();
+            other_pss.add(ps);
+            pss = pss.append(&other_pss).window(10);
+            assert!(pss.len() <= 10 + INCOMPLETE_TIMESLICES);
+            ps.timeslice += 1;
+        }
+        // which still is a full window of complete timeslices
+        let results = pss.as_results(10, 10);
+        assert!(results.verify(100.0).is_some());
+        assert_eq!(results.summary().unwrap().samples, 10);
+    }
+    #[test]
+    fn test_parallel_samples_extend() {
+        let sample = create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS));
+        let mut ps = create_test_parasample(sample, NUM_THREADS);
+        // a step of 150 timeslices (30 seconds)
+        ps.timeslice = current_timeslice() - 1000;
+        let (mut appended, mut extended) = (ParallelSamples::new(), ParallelSamples::new());
+        for _ in 0..150 {
+            let mut other_pss = ParallelSamples::new();
+            other_pss.add(ps);
+            appended = appended.append(&other_pss);
+            extended = extended.extend(&other_pss);
+            ps.timeslice += 1;
+        }
+        // append only keeps the latest 100, extend keeps them all
+        assert_eq!(appended.len(), 100);
+        assert_eq!(extended.len(), 150);
+        let summary = extended.as_results(1, usize::MAX).summary().unwrap();
+        assert_eq!(summary.samples, 150);
+        assert_eq!(
+            extended.totals().transactions,
+            150 * appended.totals().transactions / 100
+        );
+    }
+    #[test]
+    fn test_batches() {
+        let mut sample = create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS));
+        sample.requested(NUM_TRANSACTIONS as u64);
+        let mut pss = ParallelSamples::new();
+        pss.add(create_test_parasample(sample, NUM_THREADS));
+        pss.add(ParallelSample::error_sample());
+        // error samples are no samples, so they have no batch
+        let batches = pss.batches();
+        assert_eq!(batches.len(), 1);
+        let (_, requested, achieved, duration) = batches[0];
+        assert_eq!(requested, NUM_TRANSACTIONS as f64);
+        assert_eq!(achieved, NUM_TRANSACTIONS as f64 - 1.0);
+        assert!((200.0..250.0).contains(&duration));
+    }
+    #[test]
+    fn test_error_sample() {
+        let sample = create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS));
+        let mut ps = sample.to_parallel_sample();
+        let tps = ps.tot_tps();
+        let mut error = ParallelSample::error_sample();
+        error.timeslice = ps.timeslice;
+        ps.add(error).unwrap();
+        // an error is counted, but does not change tps
+        assert_eq!(ps.tot_tps(), tps);
+        let mut pss = ParallelSamples::new();
+        pss.add(ps);
+        pss.add(ParallelSample::error_sample());
+        let totals = pss.totals();
+        assert_eq!(totals.transactions, NUM_TRANSACTIONS as u64 - 1);
+        assert_eq!(totals.errors, 2);
+        assert_eq!(totals.deadlocks, 0);
+    }
+    #[test]
+    fn test_cold_latency() {
+        let mut sample = Sample::new();
+        sample.increment(Duration::milliseconds(10), Duration::zero());
+        sample.cold(Duration::milliseconds(10));
+        for _ in 0..4 {
+            sample.increment(Duration::milliseconds(1), Duration::zero());
+        }
+        sample.end();
+        let mut pss = ParallelSamples::new();
+        pss.add(sample.to_parallel_sample());
+        let totals = pss.totals();
+        assert_eq!(totals.transactions, 5);
+        assert_eq!(totals.cold, 1);
+        assert_eq!(totals.cold_latency(), Duration::milliseconds(10));
+        assert_eq!(totals.warm_latency(), Duration::milliseconds(1));
+        // no cold transactions at all
+        assert_eq!(Totals::default().cold_latency(), Duration::zero());
+    }
+    #[test]
+    fn test_rows_per_transaction() {
+        let mut sample = Sample::new();
+        for _ in 0..4 {
+            sample.increment(Duration::milliseconds(1), Duration::zero());
+            sample.affected(3);
+        }
+        sample.end();
+        let mut pss = ParallelSamples::new();
+        pss.add(sample.to_parallel_sample());
+        assert_eq!(pss.totals().rows, 12);
+        assert_eq!(pss.totals().rows_per_transaction(), 3.0);
+        assert_eq!(Totals::default().rows_per_transaction(), 0.0);
+    }
+    #[test]
     fn test_results() {
         let expected_tps = (NUM_TRANSACTIONS * NUM_THREADS * TIMESLICES_PER_SECOND) as f64;
         let expected_latency = Duration::milliseconds(WAIT_MS);
@@ -565,8 +1079,13 @@ mod tests {
         assert!(mean.latency.num_milliseconds() > 0);
         let stdev = results.std_deviation_absolute().unwrap();
         println!("stdev: {} {}", stdev.tps, stdev.latency.num_milliseconds());
-        assert!(results.verify(5.0).is_some());
-        let mean = results.mean().unwrap().clone();
+        let verified = results.verify(5.0).unwrap();
+        assert!(verified.stable);
+        assert_eq!(verified.samples, NUM_TIMESLICES);
+        assert_eq!(verified.stddev, Some((stdev.tps, stdev.latency)));
+        assert_eq!(results.summary().unwrap().stddev, verified.stddev);
+        assert!(results.mean().unwrap().stddev.is_none());
+        let mean = results.mean().unwrap();
         percent = percent_of(mean.tps, expected_tps);
         assert_eq!(percent.check_range(90.0..110.0), Ok(percent));
         percent = percent_of(
@@ -575,4 +1094,114 @@ mod tests {
         );
         assert_eq!(percent.check_range(90.0..110.0), Ok(percent));
     }
+    #[test]
+    fn test_coefficient_of_variation() {
+        assert_eq!(coefficient_of_variation(200.0, 50.0), Some(25.0));
+        assert_eq!(coefficient_of_variation(200.0, 0.0), Some(0.0));
+        assert_eq!(coefficient_of_variation(0.0, 0.0), None);
+        assert_eq!(coefficient_of_variation(-10.0, 5.0), None);
+    }
+    #[test]
+    fn test_results_zero_mean() {
+        // a workload that reports transactions without waits has a mean latency of zero, which is not stable
+        let mut results = TestResults::new(1, NUM_TIMESLICES);
+        let mut result = ParallelSample::error_sample().as_testresult();
+        result.tps = 1000.0;
+        for _ in 0..NUM_TIMESLICES {
+            results.append(result);
+        }
+        assert_eq!(results.mean().unwrap().latency, Duration::zero());
+        assert!(results.verify(100.0).is_none());
+        // as is a workload without any transactions
+        result.tps = 0.0;
+        result.latency = Duration::milliseconds(WAIT_MS);
+        let mut results = TestResults::new(1, NUM_TIMESLICES);
+        for _ in 0..NUM_TIMESLICES {
+            results.append(result);
+        }
+        assert!(results.verify(100.0).is_none());
+        // while the same latency with transactions is
+        result.tps = 1000.0;
+        let mut results = TestResults::new(1, 1);
+        results.append(result);
+        assert!(results.verify(100.0).unwrap().stable);
+    }
+    #[test]
+    fn test_results_trimmed() {
+        assert_eq!(
+            trim_sorted(vec![5, 1, 9, 3].into_iter(), 0.0),
+            vec![5, 1, 9, 3]
+        );
+        assert_eq!(trim_sorted(vec![5, 1, 9, 3].into_iter(), 25.0), vec![3, 5]);
+        // never all of them
+        assert_eq!(trim_sorted(vec![5, 1, 9].into_iter(), 50.0), vec![5]);
+        assert_eq!(trim_sorted(vec![5, 1].into_iter(), 50.0), vec![1, 5]);
+        // a single spike (e.g. a checkpoint) in 10 otherwise stable timeslices
+        let mut result = ParallelSample::error_sample().as_testresult();
+        result.latency = Duration::milliseconds(WAIT_MS);
+        let mut results = TestResults::new(1, NUM_TIMESLICES);
+        for i in 0..NUM_TIMESLICES {
+            result.tps = match i {
+                4 => 100.0,
+                _ => 1000.0,
+            };
+            results.append(result);
+        }
+        assert!(results.verify(10.0).is_none());
+        let results = results.trimmed(10.0);
+        let stable = results.verify(10.0).unwrap();
+        assert_eq!(stable.tps, 1000.0);
+        assert_eq!(stable.stddev.unwrap().0, 0.0);
+        // samples still counts all timeslices
+        assert_eq!(stable.samples, NUM_TIMESLICES);
+    }
+    #[test]
+    fn test_results_window() {
+        let sample = create_test_parasample(
+            create_test_sample(NUM_TRANSACTIONS, Duration::milliseconds(WAIT_MS)),
+            NUM_THREADS,
+        );
+        let pps = create_test_parasamples(sample, current_timeslice() - 20, NUM_TIMESLICES, 1);
+        // window > min: checked as soon as min results are in, over all results in the window
+        let results = pps.as_results(2, NUM_TIMESLICES + 5);
+        assert_eq!(results.len(), NUM_TIMESLICES);
+        assert_eq!(results.verify(100.0).unwrap().samples, NUM_TIMESLICES);
+        let results = pps.as_results(NUM_TIMESLICES + 1, NUM_TIMESLICES + 5);
+        assert!(results.verify(100.0).is_none());
+        // window < min: waits for min results, but only the latest window results count
+        let results = pps.as_results(NUM_TIMESLICES, 2);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results.verify(100.0).unwrap().samples, 2);
+        let results = pps.as_results(NUM_TIMESLICES + 1, 2);
+        assert!(results.verify(100.0).is_none());
+    }
+
+    #[test]
+    fn test_confidence() {
+        let result = |stable: bool, samples: usize, tps_stddev: f64| TestResult {
+            stable,
+            tps: 1000.0,
+            latency: Duration::microseconds(1000),
+            overhead: Duration::zero(),
+            rollback_ratio: 0.0,
+            samples,
+            stddev: Some((tps_stddev, Duration::microseconds(10))),
+            window: None,
+            trace: None,
+            confidence: None,
+        };
+        let results = TestResults::new(10, 11);
+        // only latency varies (1%) with a spread of 10%, with enough samples and stable
+        assert_eq!(results.confidence(&result(true, 10, 0.0), 10.0), 95.0);
+        // tps varies 5% with a spread of 10%, so the margin is 75%
+        assert_eq!(results.confidence(&result(true, 10, 50.0), 10.0), 75.0);
+        // half the samples, and not stabilized, halve it (again)
+        assert_eq!(results.confidence(&result(true, 5, 50.0), 10.0), 37.5);
+        assert_eq!(results.confidence(&result(false, 10, 50.0), 10.0), 37.5);
+        // far beyond spread, or without a mean, it cannot be trusted at all
+        assert_eq!(results.confidence(&result(false, 10, 500.0), 10.0), 0.0);
+        let mut degenerate = result(true, 10, 0.0);
+        degenerate.tps = 0.0;
+        assert_eq!(results.confidence(&degenerate, 10.0), 0.0);
+    }
 }
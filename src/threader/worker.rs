@@ -1,17 +1,336 @@
-use crate::threader::sample::{ParallelSamples, Sample};
-use chrono::Utc;
-use postgres::Client;
+use crate::error::PgTpsError;
+use crate::threader::sample::{ParallelSample, ParallelSamples, Sample};
+use chrono::{Duration, TimeZone, Utc};
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::types::ToSql;
+use postgres::{Client, GenericClient, SimpleQueryMessage, Statement};
 use std::sync::mpsc;
 use std::thread;
+use std::time::Instant;
 
-use super::workload::{Workload, WorkloadType};
+use super::workload::{Isolation, Workload, WorkloadType};
 
 const TABLE_NAME: &str = "pg_tps_optimizer";
+const INDEX_NAME: &str = "pg_tps_optimizer_id";
+// Columns (next to id) that a custom update expression can change (see --update-expr)
+const UPDATE_COLUMNS: [&str; 2] = ["counter bigint not null default 0", "payload text"];
+// The table --wal-stress inserts into, which grows for as long as the run takes
+const WAL_TABLE_NAME: &str = "pg_tps_optimizer_wal";
+// Every --wal-stress transaction inserts this many rows of WAL_ROW_WIDTH bytes,
+// which is below the size where toast would compress them
+const WAL_ROWS: u32 = 100;
+const WAL_ROW_WIDTH: u32 = 1000;
+// The table --insert appends to, keyed by a sequence, which grows for as long as the run takes
+pub const INSERT_TABLE_NAME: &str = "pg_tps_optimizer_insert";
+const INSERT_INDEX_NAME: &str = "pg_tps_optimizer_insert_pkey";
+// The length of a timeslice, which is the longest a worker waits before its first sample (see --stagger-start)
+const STAGGER_MS: u64 = 200;
+
+// Decides which transactions roll back instead of commit (see --rollback-ratio),
+// and in which order the statements of a transaction run (see --shuffle)
+struct Randomizer {
+    rng: fastrand::Rng,
+    // percentage of transactions that should roll back
+    ratio: f64,
+}
+
+impl Randomizer {
+    fn new(seed: u32, ratio: f64) -> Randomizer {
+        Randomizer {
+            rng: fastrand::Rng::with_seed(seed as u64),
+            ratio,
+        }
+    }
+    fn rollback(&mut self) -> bool {
+        self.ratio > 0.0 && self.rng.f64() * 100.0 < self.ratio
+    }
+    fn shuffle(&mut self, order: &mut [usize]) {
+        self.rng.shuffle(order);
+    }
+    // A random offset within a timeslice
+    fn phase(&mut self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.rng.u64(0..STAGGER_MS))
+    }
+}
+
+// The statements of a transaction: every statement updates a row (with a query per row for the simple query protocol).
+// Every statement updates the row of the worker itself, or with --shuffle the rows of the worker and the workers after it.
+// A custom query (see --query-file) is run as is, without parameters.
+// A stored procedure (see --call) is called with the id of the worker as $1, or without parameters when it has no $1.
+struct Batch {
+    query: String,
+    simple_queries: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync>>,
+    order: Vec<usize>,
+    shuffle: bool,
+    custom: bool,
+    prepared: Prepared,
+}
+
+impl Batch {
+    fn new(workload: &Workload, thread_id: u32) -> Batch {
+        let statements = workload.statements_per_transaction();
+        let rows: Vec<u32> = (0..statements)
+            .map(|k| match workload.shuffle {
+                true => thread_id + k,
+                false => thread_id,
+            })
+            .collect();
+        if let WorkloadType::WalStress = workload.w_type() {
+            let query = format!(
+                "insert into {} select $1, repeat(md5(random()::text), {}) from generate_series(1, {})",
+                WAL_TABLE_NAME,
+                WAL_ROW_WIDTH / 32,
+                WAL_ROWS
+            );
+            return Batch {
+                simple_queries: vec![query.replace("$1", &format!("'{}'", thread_id))],
+                query,
+                params: vec![workload.param_type.param(thread_id)],
+                order: vec![0],
+                shuffle: false,
+                custom: false,
+                prepared: Prepared::new(workload.prepared_variants),
+            };
+        }
+        if let WorkloadType::Insert = workload.w_type() {
+            let query = format!("insert into {} (id) values ($1)", INSERT_TABLE_NAME);
+            return Batch {
+                simple_queries: vec![query.replace("$1", &format!("'{}'", thread_id))],
+                query,
+                params: vec![workload.param_type.param(thread_id)],
+                order: vec![0],
+                shuffle: false,
+                custom: false,
+                prepared: Prepared::new(workload.prepared_variants),
+            };
+        }
+        if let WorkloadType::Call = workload.w_type() {
+            let query = format!("call {}", workload.call);
+            return Batch {
+                simple_queries: vec![query.replace("$1", &format!("'{}'", thread_id))],
+                custom: !query.contains("$1"),
+                query,
+                params: vec![workload.param_type.param(thread_id)],
+                order: vec![0],
+                shuffle: false,
+                prepared: Prepared::new(workload.prepared_variants),
+            };
+        }
+        if let Some(query) = workload.custom_query() {
+            return Batch {
+                query: query.to_string(),
+                simple_queries: rows.iter().map(|_| query.to_string()).collect(),
+                params: rows
+                    .iter()
+                    .map(|row| workload.param_type.param(*row))
+                    .collect(),
+                order: (0..statements as usize).collect(),
+                shuffle: false,
+                custom: true,
+                prepared: Prepared::new(workload.prepared_variants),
+            };
+        }
+        let query = format!(
+            "update {} set {} where id=$1",
+            TABLE_NAME,
+            workload.update_set()
+        );
+        Batch {
+            // the simple query protocol has no parameters, so the id is inlined (quoted, to fit any param type)
+            simple_queries: rows
+                .iter()
+                .map(|row| query.replace("$1", &format!("'{}'", row)))
+                .collect(),
+            query,
+            params: rows
+                .iter()
+                .map(|row| workload.param_type.param(*row))
+                .collect(),
+            order: (0..statements as usize).collect(),
+            shuffle: workload.shuffle,
+            custom: false,
+            prepared: Prepared::new(workload.prepared_variants),
+        }
+    }
+}
+
+// A quoted identifier (e.g. a tablespace name), with embedded double quotes doubled
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Set application_name to a new trace id, so that the transactions of a sample can be found back
+// in pg_stat_activity and the server log (with %a in log_line_prefix) from an exemplar (see --exemplars)
+fn tag_session(client: &mut Client) -> Result<u128, postgres::Error> {
+    let trace_id = fastrand::u128(..);
+    client.batch_execute(&format!(
+        "set application_name = '{}'",
+        application_name(trace_id)
+    ))?;
+    Ok(trace_id)
+}
+
+// Short enough to fit in application_name, which is truncated beyond 63 characters
+fn application_name(trace_id: u128) -> String {
+    format!("pg_tps_optimizer trace_id={:032x}", trace_id)
+}
+
+// The statement of a batch, prepared once per connection (see --prepared) and reused by every transaction,
+// until a reconnect (after which the statement no longer exists on the server).
+// With --prepared-variants the transactions take turns on that many variants of the statement,
+// which are each prepared once per connection, the first time they are used.
+// It is generic over the statement, so that the bookkeeping can be tested without a server.
+struct Prepared<S = Statement> {
+    statements: Vec<Option<S>>,
+    // the variant the next transaction runs
+    next: usize,
+    // how often a statement was prepared, which is once per variant per connection
+    prepares: u64,
+}
+
+// Variant 0 is the query itself, and the others differ by a comment,
+// which makes every variant a statement (and plan cache entry) of its own on the server
+fn variant_query(query: &str, variant: usize) -> String {
+    match variant {
+        0 => query.to_string(),
+        _ => format!("/* variant {} */ {}", variant, query),
+    }
+}
+
+impl Prepared {
+    fn get(&mut self, client: &mut Client, query: &str) -> Result<Statement, postgres::Error> {
+        self.next_statement(query, |query| client.prepare(query))
+    }
+}
+
+impl<S: Clone> Prepared<S> {
+    fn new(variants: u32) -> Prepared<S> {
+        Prepared {
+            statements: vec![None; variants.max(1) as usize],
+            next: 0,
+            prepares: 0,
+        }
+    }
+    // The statement of the next variant, which prepare prepares when this connection did not yet
+    fn next_statement<E>(
+        &mut self,
+        query: &str,
+        prepare: impl FnOnce(&str) -> Result<S, E>,
+    ) -> Result<S, E> {
+        let variant = self.next;
+        self.next = (self.next + 1) % self.statements.len();
+        if let Some(statement) = &self.statements[variant] {
+            return Ok(statement.clone());
+        }
+        let statement = prepare(&variant_query(query, variant))?;
+        self.prepares += 1;
+        self.statements[variant] = Some(statement.clone());
+        Ok(statement)
+    }
+    fn reset(&mut self) {
+        self.statements
+            .iter_mut()
+            .for_each(|statement| *statement = None);
+    }
+}
+
+// Time a call to the server as tightly as possible, so that client side bookkeeping
+// (building parameters, counting rows, sampling) is not part of the latency
+fn timed<T>(
+    call: impl FnOnce() -> Result<T, postgres::Error>,
+) -> Result<(T, Duration), postgres::Error> {
+    let start = Instant::now();
+    let result = call()?;
+    let elapsed = Duration::from_std(start.elapsed()).unwrap_or_else(|_| Duration::zero());
+    Ok((result, elapsed))
+}
+
+// Run statement i of batch (as prepared statement when given) on a client or in a transaction,
+// and return the number of rows it affected (see --count-rows) and the time the server call took
+fn execute<C: GenericClient>(
+    client: &mut C,
+    batch: &Batch,
+    i: usize,
+    prepared: Option<&Statement>,
+    simple_protocol: bool,
+) -> Result<(u64, Duration), postgres::Error> {
+    if simple_protocol {
+        let (messages, wait) = timed(|| client.simple_query(batch.simple_queries[i].as_str()))?;
+        let rows = messages
+            .iter()
+            .map(|message| match message {
+                SimpleQueryMessage::CommandComplete(rows) => *rows,
+                _ => 0,
+            })
+            .sum();
+        return Ok((rows, wait));
+    }
+    let param = [batch.params[i].as_ref()];
+    let params: &[&(dyn ToSql + Sync)] = match batch.custom {
+        true => &[],
+        false => &param,
+    };
+    match prepared {
+        Some(statement) => timed(|| client.execute(statement, params)),
+        None => timed(|| client.execute(batch.query.as_str(), params)),
+    }
+}
+
+// Postgres detected a deadlock and rolled the transaction back
+fn is_deadlock(err: &postgres::Error) -> bool {
+    err.code() == Some(&postgres::error::SqlState::T_R_DEADLOCK_DETECTED)
+}
+
+// Postgres could not serialize the transaction with a concurrent one (see --isolation) and rolled it back
+fn is_serialization_failure(err: &postgres::Error) -> bool {
+    err.code() == Some(&postgres::error::SqlState::T_R_SERIALIZATION_FAILURE)
+}
+
+// Run the statements of batch in one transaction, and return the time spent on the server calls,
+// the part of that spent on begin and commit, if it rolled back and the number of rows affected
+fn transaction(
+    client: &mut Client,
+    batch: &mut Batch,
+    prepared: Option<&Statement>,
+    simple_protocol: bool,
+    isolation: Isolation,
+    randomizer: &mut Randomizer,
+) -> Result<(Duration, Duration, bool, u64), postgres::Error> {
+    let (mut trans, begin) = timed(move || match isolation {
+        // a plain begin, as before --isolation existed
+        Isolation::ReadCommitted => client.transaction(),
+        _ => client
+            .build_transaction()
+            .isolation_level(isolation.level())
+            .start(),
+    })?;
+    if batch.shuffle {
+        randomizer.shuffle(&mut batch.order);
+    }
+    let mut rows: u64 = 0;
+    let mut wait = Duration::zero();
+    for i in batch.order.iter() {
+        let (affected, query) = execute(&mut trans, batch, *i, prepared, simple_protocol)?;
+        rows += affected;
+        wait += query;
+    }
+    let rolled_back = randomizer.rollback();
+    let ((), end) = timed(move || match rolled_back {
+        true => trans.rollback(),
+        false => trans.commit(),
+    })?;
+    Ok((wait + begin + end, begin + end, rolled_back, rows))
+}
 
 pub struct Worker {
     id: u32,
     tx: mpsc::Sender<ParallelSamples>,
     done: std::sync::Arc<std::sync::RwLock<bool>>,
+    connected: std::sync::Arc<std::sync::RwLock<u32>>,
+    paused: std::sync::Arc<std::sync::RwLock<bool>>,
+    // counts workers that could not connect and initialize in the first place
+    refused: std::sync::Arc<std::sync::RwLock<u32>>,
     workload: Workload,
 }
 
@@ -20,6 +339,9 @@ impl Worker {
         id: u32,
         tx: mpsc::Sender<ParallelSamples>,
         done: std::sync::Arc<std::sync::RwLock<bool>>,
+        connected: std::sync::Arc<std::sync::RwLock<u32>>,
+        paused: std::sync::Arc<std::sync::RwLock<bool>>,
+        refused: std::sync::Arc<std::sync::RwLock<u32>>,
         workload: Workload,
     ) -> Worker {
         //println!("Started new worker: {}", id);
@@ -27,30 +349,246 @@ impl Worker {
             id,
             tx,
             done,
+            connected,
+            paused,
+            refused,
             workload,
         }
     }
-    pub fn initialize(&self) -> Result<Client, Box<dyn std::error::Error>> {
-        let mut client = self.workload.client();
+    // The tablespace clause of the tables and indexes we create (see --tablespace), if any
+    fn tablespace_clause(&self) -> String {
+        match self.workload.tablespace.is_empty() {
+            true => "".to_string(),
+            false => format!(" tablespace {}", quote_ident(&self.workload.tablespace)),
+        }
+    }
+    // The first worker empties the tables of an earlier run, unless they are reused (see --reuse-table)
+    fn truncates(&self) -> bool {
+        self.id == 0 && !self.workload.reuse_table
+    }
+    // Insert the row of this worker (value is $1 or a literal), which a reused table may already have
+    fn seed_statement(&self, value: &str) -> String {
+        match self.workload.reuse_table {
+            false => format!("insert into {} values({})", TABLE_NAME, value),
+            true => format!(
+                "insert into {0} (id) select {1}::{2} where not exists (select from {0} where id = {1}::{2})",
+                TABLE_NAME,
+                value,
+                self.workload.param_type.column_type()
+            ),
+        }
+    }
+    // A table (and its index) from an earlier run may live in another tablespace, so the first worker moves them,
+    // which is cheap right after the truncate
+    fn move_statements(&self, table: &str, index: Option<&str>) -> Vec<String> {
+        if self.workload.tablespace.is_empty() || self.id != 0 {
+            return Vec::new();
+        }
+        let tablespace = quote_ident(&self.workload.tablespace);
+        let mut statements = vec![format!(
+            "alter table {} set tablespace {}",
+            table, tablespace
+        )];
+        if let Some(index) = index {
+            statements.push(format!(
+                "alter index if exists {} set tablespace {}",
+                index, tablespace
+            ));
+        }
+        statements
+    }
+    // The columns of the benchmark table, with those a custom update expression can change (see --update-expr)
+    fn columns(&self) -> String {
+        let mut columns = vec![format!("id {}", self.workload.param_type.column_type())];
+        if !self.workload.update_expr.is_empty() {
+            columns.extend(UPDATE_COLUMNS.iter().map(|column| column.to_string()));
+        }
+        columns.join(", ")
+    }
+    // A table of an earlier run may lack the columns of a custom update expression, so the first worker adds them
+    fn update_columns_statement(&self) -> Option<String> {
+        if self.workload.update_expr.is_empty() || self.id != 0 {
+            return None;
+        }
+        let columns: Vec<String> = UPDATE_COLUMNS
+            .iter()
+            .map(|column| format!("add column if not exists {}", column))
+            .collect();
+        Some(format!("alter table {} {}", TABLE_NAME, columns.join(", ")))
+    }
+    // Without an index on id every update is a seq scan, which measures the scan more than the update.
+    // The index is created (or dropped with --without-index) once, by the first worker.
+    fn index_statement(&self) -> String {
+        match self.workload.without_index {
+            true => format!("drop index if exists {}", INDEX_NAME),
+            false => format!(
+                "create index if not exists {} on {} (id){}",
+                INDEX_NAME,
+                TABLE_NAME,
+                self.tablespace_clause()
+            ),
+        }
+    }
+    // The table for --wal-stress or --insert, which is emptied (and its sequence reset) once, by the first worker
+    fn append_table_statements(&self) -> Vec<String> {
+        let column_type = self.workload.param_type.column_type();
+        let tablespace = self.tablespace_clause();
+        // the index of the primary key goes there too
+        let index_tablespace = match tablespace.is_empty() {
+            true => "".to_string(),
+            false => format!(" using index{}", tablespace),
+        };
+        let (table, columns, index) = match self.workload.wal_stress {
+            true => (
+                WAL_TABLE_NAME,
+                format!("id {}, payload text", column_type),
+                None,
+            ),
+            false => (
+                INSERT_TABLE_NAME,
+                format!(
+                    "seq bigserial primary key{}, id {}, inserted timestamptz default now()",
+                    index_tablespace, column_type
+                ),
+                Some(INSERT_INDEX_NAME),
+            ),
+        };
+        let mut statements = vec![format!(
+            "create table if not exists {} ({}){}",
+            table, columns, tablespace
+        )];
+        if self.truncates() {
+            statements.push(format!("truncate table {} restart identity", table));
+        }
+        statements.extend(self.move_statements(table, index));
+        statements
+    }
+    pub fn initialize(&self) -> Result<Client, PgTpsError> {
+        if self.workload.wal_stress || self.workload.insert {
+            let mut direct = self.workload.direct_client()?;
+            direct.batch_execute(self.append_table_statements().join(";").as_str())?;
+            return self.workload.client();
+        }
+        if self.workload.pgbouncer {
+            // DDL and seeding with the simple query protocol on a direct connection
+            let mut direct = self.workload.direct_client()?;
+            let mut statements = vec![format!(
+                "create table if not exists {} ({}){}",
+                TABLE_NAME,
+                self.columns(),
+                self.tablespace_clause()
+            )];
+            if self.id == 0 {
+                statements.push(self.index_statement());
+            }
+            if self.truncates() {
+                statements.push(format!("truncate table {}", TABLE_NAME));
+            }
+            statements.extend(self.update_columns_statement());
+            statements.extend(self.move_statements(TABLE_NAME, Some(INDEX_NAME)));
+            statements.push(self.seed_statement(&format!("'{}'", self.id)));
+            direct.batch_execute(statements.join(";").as_str())?;
+            return self.workload.client();
+        }
+        let mut client = self.workload.client()?;
+        if self.workload.temp_table {
+            // a table of our own, which is dropped on disconnect and shadows the shared table
+            let mut statements = vec![format!(
+                "create temp table {0} ({1}){3}; insert into {0} values('{2}')",
+                TABLE_NAME,
+                self.columns(),
+                self.id,
+                self.tablespace_clause()
+            )];
+            if !self.workload.without_index {
+                // unnamed, as temp tables of different sessions would otherwise need unique index names
+                statements.push(format!(
+                    "create index on {} (id){}",
+                    TABLE_NAME,
+                    self.tablespace_clause()
+                ));
+            }
+            client.batch_execute(statements.join(";").as_str())?;
+            return Ok(client);
+        }
         client.query(
-            format!("create table if not exists {} (id oid)", TABLE_NAME).as_str(),
+            format!(
+                "create table if not exists {} ({}){}",
+                TABLE_NAME,
+                self.columns(),
+                self.tablespace_clause()
+            )
+            .as_str(),
             &[],
         )?;
         if self.id == 0 {
+            client.query(self.index_statement().as_str(), &[])?;
+        }
+        if self.truncates() {
             client.query(format!("truncate table {}", TABLE_NAME).as_str(), &[])?;
         }
+        if let Some(statement) = self.update_columns_statement() {
+            client.batch_execute(statement.as_str())?;
+        }
+        for statement in self.move_statements(TABLE_NAME, Some(INDEX_NAME)) {
+            client.batch_execute(statement.as_str())?;
+        }
         client.query(
-            format!("insert into {} values($1)", TABLE_NAME).as_str(),
-            &[&self.id],
+            self.seed_statement("$1").as_str(),
+            &[self.workload.param_type.param(self.id).as_ref()],
         )?;
 
         Ok(client)
     }
-    pub fn procedure(self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut tps: f64 = 1000_f64;
-
-        //Sleep 100 milliseconds
-        let mut client = self.initialize()?;
+    // A new session, which needs its own temp table (see --temp-table)
+    fn reconnect(&self) -> Result<Client, PgTpsError> {
+        match self.workload.temp_table {
+            true => self.initialize(),
+            false => self.workload.client(),
+        }
+    }
+    fn is_notifier(&self) -> bool {
+        matches!(self.workload.w_type(), WorkloadType::Notify) && self.id.is_multiple_of(2)
+    }
+    // Connect and run the workload until done, while counting this worker as connected
+    pub fn procedure(self) -> Result<(), PgTpsError> {
+        let client = match self.initialize() {
+            Ok(client) => client,
+            Err(err) => {
+                if let Ok(mut refused) = self.refused.write() {
+                    // lets the Threader know this worker will never connect
+                    *refused += 1;
+                }
+                return Err(err);
+            }
+        };
+        if let Ok(mut connected) = self.connected.write() {
+            // lets the Threader know this worker is connected and initialized
+            *connected += 1;
+        }
+        let result = self.run(client);
+        if let Ok(mut connected) = self.connected.write() {
+            *connected -= 1;
+        }
+        result
+    }
+    fn run(&self, mut client: Client) -> Result<(), PgTpsError> {
+        // the number of queries for the next sample, adapted to the tps of the previous sample
+        let mut batch_size: u64 = match (self.workload.fixed_batch, self.workload.initial_batch) {
+            (0, 0) => 100,
+            (0, initial_batch) => initial_batch as u64,
+            (fixed_batch, _) => fixed_batch as u64,
+        };
+        let mut randomizer = Randomizer::new(self.id, self.workload.rollback_ratio);
+        let mut batch = Batch::new(&self.workload, self.id);
+        let per_session = self.workload.transactions_per_session as u64;
+        let mut session_transactions: u64 = 0;
+        // the next transaction is the first of a session (see --cold-latency)
+        let mut cold = true;
+        if self.workload.stagger_start {
+            // workers that started at the same moment would otherwise begin and end their samples in step
+            thread::sleep(randomizer.phase());
+        }
 
         loop {
             if let Ok(done) = self.done.read() {
@@ -59,24 +597,60 @@ impl Worker {
                     break;
                 }
             }
+            if let Ok(paused) = self.paused.read() {
+                // paused is true in between bursts (see --burst-off)
+                if *paused {
+                    thread::sleep(std::time::Duration::from_millis(10));
+                    continue;
+                }
+            }
+            let mut num_queries = batch_size;
+            if per_session > 0 {
+                // never run more transactions than the session has left
+                num_queries = num_queries.min(per_session - session_transactions);
+            }
             match sample(
                 &mut client,
-                self.workload.w_type(),
-                (tps / 10_f64) as u64,
+                &self.workload,
+                num_queries,
                 self.id,
+                &mut batch,
+                &mut randomizer,
+                &mut cold,
             ) {
                 Ok(sample) => {
                     //tps = samples.tot_tps_singlethread() as u64;
                     let mut pss = ParallelSamples::new();
-                    pss.add(sample.to_parallel_sample());
+                    if self.is_notifier() {
+                        // only notifications that are received are counted
+                        pss.add(sample.without_transactions().to_parallel_sample());
+                    } else {
+                        pss.add(sample.to_parallel_sample());
+                    }
                     self.tx.send(pss)?;
-                    tps = sample.tps();
+                    if self.workload.fixed_batch == 0 {
+                        batch_size = (sample.tps() / 10_f64) as u64;
+                    }
+                    session_transactions += sample.transactions();
+                    if per_session > 0 && session_transactions >= per_session {
+                        // session is used up, reconnect right away (no error, so no backoff)
+                        client = self.reconnect()?;
+                        batch.prepared.reset();
+                        session_transactions = 0;
+                        cold = true;
+                    }
                 }
                 Err(err) => {
                     println!("Error: {}", &err);
+                    let mut pss = ParallelSamples::new();
+                    pss.add(ParallelSample::error_sample());
+                    self.tx.send(pss)?;
                     let sleeptime = std::time::Duration::from_millis(100);
                     thread::sleep(sleeptime);
-                    client = self.workload.client();
+                    client = self.reconnect()?;
+                    batch.prepared.reset();
+                    session_transactions = 0;
+                    cold = true;
                 }
             };
         }
@@ -86,44 +660,475 @@ impl Worker {
 
 fn sample(
     client: &mut Client,
-    w_type: WorkloadType,
+    workload: &Workload,
     mut num_queries: u64,
     thread_id: u32,
+    batch: &mut Batch,
+    randomizer: &mut Randomizer,
+    cold: &mut bool,
 ) -> Result<Sample, postgres::Error> {
     if num_queries < 1 {
         num_queries = 1;
     }
+    let w_type = workload.w_type();
+    let simple_protocol = workload.pgbouncer;
+    if let WorkloadType::Notify = w_type {
+        return notify_sample(client, num_queries, thread_id);
+    }
+    let statements = workload.statements_per_transaction();
+    // with --count-statements every statement counts (with its share of the latency), otherwise every transaction
+    let counted = match workload.count_statements {
+        true => statements,
+        false => 1,
+    };
+    // the session is tagged before the sample starts, so that it is not part of any latency
+    let trace_id = match workload.exemplars {
+        true => Some(tag_session(client)?),
+        false => None,
+    };
     let mut s = Sample::new();
-    let query = format!("update {} set id=$1 where id=$1", TABLE_NAME);
+    s.requested(num_queries);
 
-    for _x in 0..num_queries {
-        let start = Utc::now();
-        match w_type {
+    for x in 0..(num_queries / counted as u64).max(1) {
+        // only a transaction the server rolled back is timed as a whole, since it has no server calls to time
+        let start = Instant::now();
+        let mut rolled_back = false;
+        let mut deadlocked = false;
+        let mut rows: u64 = 0;
+        // the time spent on server calls, and the part of that spent on begin and commit, as opposed to running the query
+        let (wait, overhead) = match w_type {
             WorkloadType::Prepared => {
-                let prep = client.prepare(query.as_str())?;
-                client.query(&prep, &[&thread_id])?;
+                let prep = batch.prepared.get(client, &batch.query)?;
+                let (affected, wait) = execute(client, batch, 0, Some(&prep), false)?;
+                rows = affected;
+                (wait, Duration::zero())
             }
-            WorkloadType::Transactional => {
-                let mut trans = client.transaction()?;
-                if !query.is_empty() {
-                    trans.query(query.as_str(), &[&thread_id])?;
+            WorkloadType::Transactional | WorkloadType::PreparedTransactional => {
+                let prep = match w_type {
+                    WorkloadType::PreparedTransactional => {
+                        Some(batch.prepared.get(client, &batch.query)?)
+                    }
+                    _ => None,
+                };
+                match transaction(
+                    client,
+                    batch,
+                    prep.as_ref(),
+                    simple_protocol,
+                    workload.isolation,
+                    randomizer,
+                ) {
+                    Ok((wait, overhead, rollback, affected)) => {
+                        rolled_back = rollback;
+                        rows = affected;
+                        (wait, overhead)
+                    }
+                    // the server rolled back (see --shuffle), which is a result rather than an error
+                    Err(err) if is_deadlock(&err) => {
+                        deadlocked = true;
+                        (
+                            Duration::from_std(start.elapsed())
+                                .unwrap_or_else(|_| Duration::zero()),
+                            Duration::zero(),
+                        )
+                    }
+                    Err(err) if is_serialization_failure(&err) => {
+                        rolled_back = true;
+                        (
+                            Duration::from_std(start.elapsed())
+                                .unwrap_or_else(|_| Duration::zero()),
+                            Duration::zero(),
+                        )
+                    }
+                    Err(err) => return Err(err),
                 }
-                trans.commit()?;
             }
-            WorkloadType::PreparedTransactional => {
-                let mut trans = client.transaction()?;
-                if !query.is_empty() {
-                    let prep = trans.prepare(&query)?;
-                    let _row = trans.query(&prep, &[&thread_id]);
-                }
-                trans.commit()?;
+            WorkloadType::Default | WorkloadType::WalStress | WorkloadType::Insert => {
+                let (affected, wait) = execute(client, batch, 0, None, simple_protocol)?;
+                rows = affected;
+                (wait, Duration::zero())
+            }
+            WorkloadType::Call => {
+                // never in a transaction block (not even with --prepared), since the procedure may commit itself
+                let prep = match workload.prepared_statements() {
+                    true => Some(batch.prepared.get(client, &batch.query)?),
+                    false => None,
+                };
+                let (affected, wait) = execute(client, batch, 0, prep.as_ref(), simple_protocol)?;
+                rows = affected;
+                (wait, Duration::zero())
+            }
+            WorkloadType::Notify => unreachable!("notify workloads are sampled by notify_sample"),
+        };
+        s.affected(rows);
+        if let (0, Some(trace_id)) = (x, trace_id) {
+            s.traced(trace_id, wait);
+        }
+        for _ in 0..counted {
+            s.increment(wait / counted as i32, overhead / counted as i32);
+            if *cold {
+                s.cold(wait / counted as i32);
             }
-            WorkloadType::Default => {
-                client.query(query.as_str(), &[&thread_id])?;
+            if deadlocked {
+                s.deadlocked();
+            } else if rolled_back {
+                s.rolled_back();
             }
         }
-        s.increment(Utc::now() - start);
+        *cold = false;
+        if !workload.think_time.is_zero() {
+            // after wait was taken, so it lowers tps but not latency
+            thread::sleep(workload.think_time);
+        }
     }
     s.end();
     Ok(s)
 }
+
+// Even workers send notifications (with the send time as payload).
+// Odd workers listen for 100msec and record the delivery latency of every notification.
+fn notify_sample(
+    client: &mut Client,
+    num_notifications: u64,
+    thread_id: u32,
+) -> Result<Sample, postgres::Error> {
+    let mut s = Sample::new();
+    if thread_id.is_multiple_of(2) {
+        for _x in 0..num_notifications {
+            let payload = Utc::now().timestamp_micros().to_string();
+            let (_, wait) =
+                timed(|| client.query("select pg_notify($1, $2)", &[&TABLE_NAME, &payload]))?;
+            s.increment(wait, Duration::zero());
+        }
+        s.end();
+        return Ok(s);
+    }
+    client.batch_execute(format!("listen {}", TABLE_NAME).as_str())?;
+    let end_time = Utc::now() + Duration::milliseconds(100);
+    let mut notifications = client.notifications();
+    while let Ok(timeout) = (end_time - Utc::now()).to_std() {
+        match notifications.timeout_iter(timeout).next()? {
+            Some(notification) => {
+                let sent = notification
+                    .payload()
+                    .parse::<i64>()
+                    .ok()
+                    .and_then(|micros| Utc.timestamp_micros(micros).single());
+                if let Some(sent) = sent {
+                    s.increment(Utc::now() - sent, Duration::zero());
+                }
+            }
+            None => break,
+        }
+    }
+    s.end();
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsn::Dsn;
+
+    // A worker that is never started, to test the statements it would run
+    fn test_worker(id: u32, workload: &Workload) -> Worker {
+        let (tx, _) = mpsc::channel();
+        let flag = || std::sync::Arc::new(std::sync::RwLock::new(false));
+        let counter = || std::sync::Arc::new(std::sync::RwLock::new(0));
+        Worker::new(
+            id,
+            tx,
+            flag(),
+            counter(),
+            flag(),
+            counter(),
+            workload.clone(),
+        )
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut workload = Workload::new(Dsn::from_string(""), "".to_string(), true, false);
+        workload.batch_commit = 3;
+        let batch = Batch::new(&workload, 5);
+        assert_eq!(batch.order, vec![0, 1, 2]);
+        assert!(batch
+            .simple_queries
+            .iter()
+            .all(|q| q.ends_with("where id='5'")));
+        workload.shuffle = true;
+        let mut batch = Batch::new(&workload, 5);
+        assert_eq!(
+            batch.simple_queries[2],
+            format!("update {0} set id='7' where id='7'", TABLE_NAME)
+        );
+        // every order has all statements exactly once
+        let mut randomizer = Randomizer::new(5, 0.0);
+        randomizer.shuffle(&mut batch.order);
+        batch.order.sort();
+        assert_eq!(batch.order, vec![0, 1, 2]);
+        // the phase of a worker stays within a timeslice, and differs per worker
+        assert!((0..10).all(|_| randomizer.phase().as_millis() < STAGGER_MS as u128));
+        let phases: Vec<_> = (0..10).map(|id| Randomizer::new(id, 0.0).phase()).collect();
+        assert!(phases.iter().any(|phase| *phase != phases[0]));
+    }
+
+    // The statements this session prepared (see Prepared), counted with the simple query protocol which prepares none itself
+    fn prepared_statements(client: &mut Client) -> Result<String, postgres::Error> {
+        let messages = client.simple_query("select count(*) from pg_prepared_statements")?;
+        Ok(messages
+            .iter()
+            .find_map(|message| match message {
+                SimpleQueryMessage::Row(row) => row.get(0).map(|count| count.to_string()),
+                _ => None,
+            })
+            .unwrap_or_default())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_prepared_once() -> Result<(), PgTpsError> {
+        let constr = crate::generic::get_env_str("", "TEST_CONNSTR", "");
+        if constr.is_empty() {
+            return Ok(());
+        }
+        for (transactional, variants) in [(false, 1), (true, 1), (false, 3)] {
+            let mut workload = Workload::new(
+                Dsn::from_string(constr.as_str()),
+                "".to_string(),
+                transactional,
+                true,
+            );
+            workload.prepared_variants = variants;
+            let mut batch = Batch::new(&workload, 5);
+            let mut randomizer = Randomizer::new(5, 0.0);
+            let mut cold = true;
+            for connection in 1..=2 {
+                // a table of this session only, which shadows the benchmark table
+                let mut client = workload.client()?;
+                client.batch_execute(&format!(
+                    "create temp table {0} (id oid); insert into {0} values (5)",
+                    TABLE_NAME
+                ))?;
+                batch.prepared.reset();
+                for _ in 0..3 {
+                    let s = sample(
+                        &mut client,
+                        &workload,
+                        50,
+                        5,
+                        &mut batch,
+                        &mut randomizer,
+                        &mut cold,
+                    )?;
+                    assert_eq!(s.transactions(), 50);
+                }
+                // once per variant per connection, not once per transaction
+                assert_eq!(batch.prepared.prepares, connection * variants as u64);
+                assert_eq!(prepared_statements(&mut client)?, variants.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_variant_query() {
+        let query = format!("update {} set id=id where id=$1", TABLE_NAME);
+        assert_eq!(variant_query(&query, 0), query);
+        assert_eq!(
+            variant_query(&query, 2),
+            format!("/* variant 2 */ {}", query)
+        );
+        let prepared: Prepared = Prepared::new(0);
+        assert_eq!(prepared.statements.len(), 1);
+    }
+
+    #[test]
+    fn test_prepared() {
+        for variants in [1, 3] {
+            let mut prepared: Prepared<String> = Prepared::new(variants);
+            let mut server: Vec<String> = Vec::new();
+            for connection in 1..=2 {
+                // a reconnect, after which the statements no longer exist on the server
+                prepared.reset();
+                for _ in 0..150 {
+                    let statement = prepared
+                        .next_statement("select $1", |query| {
+                            server.push(query.to_string());
+                            Ok::<_, ()>(query.to_string())
+                        })
+                        .unwrap();
+                    assert!(statement.ends_with("select $1"));
+                }
+                // once per variant per connection, not once per transaction
+                assert_eq!(prepared.prepares, connection * variants as u64);
+            }
+            assert_eq!(server.len(), 2 * variants as usize);
+            assert_eq!(
+                server[variants as usize - 1],
+                variant_query("select $1", variants as usize - 1)
+            );
+        }
+        // a failed prepare is tried again by the next transaction of that variant
+        let mut prepared: Prepared<String> = Prepared::new(1);
+        assert!(prepared.next_statement("select 1", |_| Err(())).is_err());
+        assert_eq!(prepared.prepares, 0);
+        assert!(prepared
+            .next_statement("select 1", |query| Ok::<_, ()>(query.to_string()))
+            .is_ok());
+        assert_eq!(prepared.prepares, 1);
+    }
+
+    #[test]
+    fn test_application_name() {
+        let name = application_name(0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(
+            name,
+            "pg_tps_optimizer trace_id=4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert!(application_name(1).len() < 64);
+    }
+
+    #[test]
+    fn test_wal_stress_batch() {
+        let mut workload = Workload::new(Dsn::from_string(""), "".to_string(), true, false);
+        workload.wal_stress = true;
+        workload.batch_commit = 3;
+        // a single insert of many wide rows, regardless of the transactional options
+        let batch = Batch::new(&workload, 5);
+        assert_eq!(batch.order, vec![0]);
+        assert!(batch
+            .query
+            .starts_with(&format!("insert into {} select $1,", WAL_TABLE_NAME)));
+        assert!(batch.simple_queries[0]
+            .starts_with(&format!("insert into {} select '5',", WAL_TABLE_NAME)));
+    }
+
+    #[test]
+    fn test_call_batch() {
+        let mut workload = Workload::new(Dsn::from_string(""), "".to_string(), false, true);
+        workload.call = "my_proc($1, 'x')".to_string();
+        let batch = Batch::new(&workload, 5);
+        assert_eq!(batch.query, "call my_proc($1, 'x')");
+        assert_eq!(batch.simple_queries, vec!["call my_proc('5', 'x')"]);
+        assert!(!batch.custom);
+        // without $1 the procedure is called without parameters
+        workload.call = "my_proc()".to_string();
+        assert!(Batch::new(&workload, 5).custom);
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let mut workload = Workload::new(Dsn::from_string(""), "".to_string(), false, true);
+        workload.insert = true;
+        let batch = Batch::new(&workload, 5);
+        assert_eq!(batch.order, vec![0]);
+        assert_eq!(
+            batch.query,
+            format!("insert into {} (id) values ($1)", INSERT_TABLE_NAME)
+        );
+        assert_eq!(
+            batch.simple_queries[0],
+            format!("insert into {} (id) values ('5')", INSERT_TABLE_NAME)
+        );
+        let first = test_worker(0, &workload);
+        let statements = first.append_table_statements();
+        assert!(statements[0].contains("seq bigserial primary key"));
+        assert_eq!(
+            statements[1],
+            format!("truncate table {} restart identity", INSERT_TABLE_NAME)
+        );
+        // only the first worker empties the table
+        let other = test_worker(1, &workload);
+        assert_eq!(other.append_table_statements().len(), 1);
+    }
+
+    #[test]
+    fn test_reuse_table() {
+        let mut workload = Workload::new(Dsn::from_string(""), "".to_string(), false, false);
+        workload.insert = true;
+        let first = |workload: &Workload| test_worker(0, workload);
+        assert!(first(&workload).truncates());
+        assert_eq!(
+            first(&workload).seed_statement("$1"),
+            format!("insert into {} values($1)", TABLE_NAME)
+        );
+        workload.reuse_table = true;
+        assert!(!first(&workload).truncates());
+        // the rows of an earlier run stay, and only a missing row is added
+        assert_eq!(first(&workload).append_table_statements().len(), 1);
+        assert_eq!(
+            first(&workload).seed_statement("'0'"),
+            format!(
+                "insert into {0} (id) select '0'::oid where not exists (select from {0} where id = '0'::oid)",
+                TABLE_NAME
+            )
+        );
+    }
+
+    #[test]
+    fn test_tablespace() {
+        assert_eq!(quote_ident("fast\"ssd"), "\"fast\"\"ssd\"");
+        let mut workload = Workload::new(Dsn::from_string(""), "".to_string(), false, false);
+        workload.tablespace = "ssd".to_string();
+        let first = test_worker(0, &workload);
+        assert!(first.index_statement().ends_with("(id) tablespace \"ssd\""));
+        assert_eq!(
+            first.move_statements(TABLE_NAME, Some(INDEX_NAME)),
+            vec![
+                format!("alter table {} set tablespace \"ssd\"", TABLE_NAME),
+                format!(
+                    "alter index if exists {} set tablespace \"ssd\"",
+                    INDEX_NAME
+                ),
+            ]
+        );
+        workload.insert = true;
+        let first = test_worker(0, &workload);
+        let statements = first.append_table_statements();
+        assert!(statements[0].contains("primary key using index tablespace \"ssd\","));
+        assert!(statements[0].ends_with(") tablespace \"ssd\""));
+        assert_eq!(statements.len(), 4);
+        // only the first worker moves the tables
+        let other = test_worker(1, &workload);
+        assert!(other.move_statements(TABLE_NAME, None).is_empty());
+    }
+
+    #[test]
+    fn test_update_expr() {
+        let mut workload = Workload::new(Dsn::from_string(""), "".to_string(), false, false);
+        // the default update sets id to what it already is
+        let batch = Batch::new(&workload, 5);
+        assert_eq!(
+            batch.query,
+            format!("update {} set id=$1 where id=$1", TABLE_NAME)
+        );
+        workload.update_expr = "counter = counter + 1, payload = $1".to_string();
+        let batch = Batch::new(&workload, 5);
+        assert_eq!(
+            batch.query,
+            format!(
+                "update {} set counter = counter + 1, payload = $1 where id=$1",
+                TABLE_NAME
+            )
+        );
+        assert_eq!(
+            batch.simple_queries[0],
+            format!(
+                "update {} set counter = counter + 1, payload = '5' where id='5'",
+                TABLE_NAME
+            )
+        );
+        let first = test_worker(0, &workload);
+        assert_eq!(
+            first.columns(),
+            "id oid, counter bigint not null default 0, payload text"
+        );
+        assert!(first
+            .update_columns_statement()
+            .unwrap()
+            .ends_with("add column if not exists counter bigint not null default 0, add column if not exists payload text"));
+        let other = test_worker(1, &workload);
+        assert_eq!(other.update_columns_statement(), None);
+    }
+}
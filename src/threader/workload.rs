@@ -1,46 +1,179 @@
 use crate::dsn;
-use postgres::Client;
+use crate::error::PgTpsError;
+use postgres::types::ToSql;
+use postgres::{Client, IsolationLevel};
 
+// The application_name of the connections of the clients, so that their backends can be told apart (e.g. in pg_stat_activity)
+pub const APPLICATION_NAME: &str = "pg_tps_optimizer";
+
+#[derive(Clone)]
 pub struct Workload {
     dsn: dsn::Dsn,
     query: String,
     transactional: bool,
     prepared: bool,
+    // 0 means a worker keeps its session for the entire run
+    pub transactions_per_session: u32,
+    pub notify: bool,
+    pub pin_cpus: bool,
+    // pgbouncer (transaction pooling) compatible: no prepared statements, simple query protocol
+    pub pgbouncer: bool,
+    // connection for creating and seeding the benchmark table (bypassing a pooler)
+    pub direct_dsn: dsn::Dsn,
+    // the type of the id column, and of the $1 parameter it is queried with
+    pub param_type: ParamType,
+    // percentage of transactions (in transactional workloads) that roll back instead of commit
+    pub rollback_ratio: f64,
+    // queries in the first sample of a worker (0 means 100), after which it adapts to the tps
+    pub initial_batch: u32,
+    // queries in every sample of a worker, instead of adapting to the tps (0 means adaptive)
+    pub fixed_batch: u32,
+    // every worker uses a temp table of its own instead of the shared table
+    pub temp_table: bool,
+    // no index on the id column, so every statement does a seq scan (see --without-index)
+    pub without_index: bool,
+    // statements per transaction in transactional workloads (0 means 1)
+    pub batch_commit: u32,
+    // the number of variants of the prepared statement that transactions take turns on (see --prepared-variants)
+    pub prepared_variants: u32,
+    // count every statement instead of every transaction in tps and latency
+    pub count_statements: bool,
+    // the statements of a transaction update the rows of the next workers too, in random order (see --shuffle)
+    pub shuffle: bool,
+    // pause of every worker after every transaction, which is not part of the latency (see --think-time)
+    pub think_time: std::time::Duration,
+    // every worker waits a random part of a timeslice before its first sample (see --stagger-start)
+    pub stagger_start: bool,
+    // the isolation level of every transaction in transactional workloads (see --isolation)
+    pub isolation: Isolation,
+    // workers insert wide rows to generate as much wal as possible (see --wal-stress)
+    pub wal_stress: bool,
+    // workers insert a new row per transaction instead of updating their own (see --insert)
+    pub insert: bool,
+    // the SET clause of the built in update, where empty means id=$1 (see --update-expr)
+    pub update_expr: String,
+    // the stored procedure workers CALL instead of updating the benchmark table, if any (see --call)
+    pub call: String,
+    // the benchmark tables keep the rows of earlier runs instead of being truncated (see --reuse-table)
+    pub reuse_table: bool,
+    // the tablespace of the benchmark tables and indexes, where empty means the default (see --tablespace)
+    pub tablespace: String,
+    // the first transaction of every sample is tagged with a trace id (see --exemplars)
+    pub exemplars: bool,
 }
 
 impl Workload {
     pub fn new(dsn: dsn::Dsn, query: String, transactional: bool, prepared: bool) -> Workload {
+        let dsn = dsn.with_application_name(APPLICATION_NAME);
         Workload {
+            direct_dsn: dsn.clone(),
             dsn,
             query,
             transactional,
             prepared,
-        }
-    }
-    pub fn clone(&self) -> Workload {
-        Workload {
-            dsn: self.dsn.clone(),
-            query: self.query.clone(),
-            transactional: self.transactional,
-            prepared: self.prepared,
+            transactions_per_session: 0,
+            notify: false,
+            pin_cpus: false,
+            pgbouncer: false,
+            param_type: ParamType::Oid,
+            rollback_ratio: 0.0,
+            initial_batch: 0,
+            fixed_batch: 0,
+            temp_table: false,
+            without_index: false,
+            batch_commit: 0,
+            prepared_variants: 1,
+            count_statements: false,
+            shuffle: false,
+            think_time: std::time::Duration::ZERO,
+            stagger_start: false,
+            isolation: Isolation::ReadCommitted,
+            wal_stress: false,
+            insert: false,
+            update_expr: String::new(),
+            call: String::new(),
+            reuse_table: false,
+            tablespace: String::new(),
+            exemplars: false,
         }
     }
     pub fn as_string(&self) -> String {
         format!(
-            "dsn:{}\ntransactional: {}\nprepared: {}",
+            "dsn:{}\ntransactional: {}\nprepared: {}\ntransactions per session: {}\nnotify: {}\npgbouncer: {}\nparam type: {}\nindexed: {}\nrollback ratio: {}%\nisolation: {}\nthink time: {}ms\nwal stress: {}\ninsert: {}\ncall: {}\ntablespace: {}\nreuse table: {}",
             self.dsn.debug(),
             self.transactional,
-            self.prepared
+            self.prepared,
+            self.transactions_per_session,
+            self.notify,
+            self.pgbouncer,
+            self.param_type.column_type(),
+            !self.without_index,
+            self.rollback_ratio,
+            self.isolation.as_str(),
+            self.think_time.as_millis(),
+            self.wal_stress,
+            self.insert,
+            match self.call.is_empty() {
+                true => "no",
+                false => self.call.as_str(),
+            },
+            match self.tablespace.is_empty() {
+                true => "default",
+                false => self.tablespace.as_str(),
+            },
+            self.reuse_table
         )
     }
-    pub fn client(&self) -> Client {
-        self.dsn
-            .clone()
-            .client()
-            .expect("Cloning a client should never result in an error")
+    // The SET clause of the built in update, which by default sets id to what it already is
+    pub fn update_set(&self) -> &str {
+        match self.update_expr.is_empty() {
+            true => "id=$1",
+            false => self.update_expr.as_str(),
+        }
+    }
+    // The query workers run instead of the built in update, if any (see --query and --query-file)
+    pub fn custom_query(&self) -> Option<&str> {
+        match self.query.is_empty() {
+            true => None,
+            false => Some(self.query.as_str()),
+        }
+    }
+    pub fn application_name(&self) -> String {
+        self.dsn.application_name()
+    }
+    pub fn client(&self) -> Result<Client, PgTpsError> {
+        self.dsn.clone().client()
+    }
+    pub fn direct_client(&self) -> Result<Client, PgTpsError> {
+        self.direct_dsn.clone().client()
+    }
+    // the number of statements that are committed together (see --batch-commit)
+    pub fn statements_per_transaction(&self) -> u32 {
+        match self.w_type() {
+            WorkloadType::Transactional | WorkloadType::PreparedTransactional => {
+                self.batch_commit.max(1)
+            }
+            _ => 1,
+        }
+    }
+    // pgbouncer in transaction pooling mode cannot handle server side prepared statements
+    pub fn prepared_statements(&self) -> bool {
+        self.prepared && !self.pgbouncer
     }
     pub fn w_type(&self) -> WorkloadType {
-        match (self.transactional, self.prepared) {
+        if self.notify {
+            return WorkloadType::Notify;
+        }
+        if self.wal_stress {
+            return WorkloadType::WalStress;
+        }
+        if self.insert {
+            return WorkloadType::Insert;
+        }
+        if !self.call.is_empty() {
+            return WorkloadType::Call;
+        }
+        match (self.transactional, self.prepared_statements()) {
             (false, false) => WorkloadType::Default,
             (true, false) => WorkloadType::Transactional,
             (false, true) => WorkloadType::Prepared,
@@ -54,4 +187,120 @@ pub enum WorkloadType {
     Transactional,
     Prepared,
     PreparedTransactional,
+    // half of the workers NOTIFY, the other half LISTEN and count what they receive
+    Notify,
+    // every transaction inserts a batch of wide rows, to stress wal generation and replication
+    WalStress,
+    // every transaction inserts a new row into an ever growing table (an append only log)
+    Insert,
+    // every transaction is a CALL of a stored procedure, outside of a transaction block so that it can commit itself
+    Call,
+}
+
+// How the id of a worker is stored in the benchmark table and bound as $1 (see --param-type)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamType {
+    Oid,
+    Int4,
+    Int8,
+    Text,
+}
+
+impl ParamType {
+    pub fn from_string(param_type: &str) -> Result<ParamType, PgTpsError> {
+        match param_type.to_lowercase().as_str() {
+            "oid" => Ok(ParamType::Oid),
+            "int4" => Ok(ParamType::Int4),
+            "int8" => Ok(ParamType::Int8),
+            "text" => Ok(ParamType::Text),
+            _ => Err(PgTpsError::Config(format!(
+                "invalid value for param_type: {} is not one of oid, int4, int8 or text",
+                param_type
+            ))),
+        }
+    }
+    pub fn column_type(&self) -> &'static str {
+        match self {
+            ParamType::Oid => "oid",
+            ParamType::Int4 => "int4",
+            ParamType::Int8 => "int8",
+            ParamType::Text => "text",
+        }
+    }
+    // The id as a value that binds to a parameter of column_type
+    pub fn param(&self, id: u32) -> Box<dyn ToSql + Sync> {
+        match self {
+            ParamType::Oid => Box::new(id),
+            ParamType::Int4 => Box::new(id as i32),
+            ParamType::Int8 => Box::new(id as i64),
+            ParamType::Text => Box::new(id.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Isolation {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl Isolation {
+    pub fn from_string(isolation: &str) -> Result<Isolation, PgTpsError> {
+        match isolation.to_lowercase().replace('_', "-").as_str() {
+            "read-committed" => Ok(Isolation::ReadCommitted),
+            "repeatable-read" => Ok(Isolation::RepeatableRead),
+            "serializable" => Ok(Isolation::Serializable),
+            _ => Err(PgTpsError::Config(format!(
+                "invalid value for isolation: {} is not one of read-committed, repeatable-read or serializable",
+                isolation
+            ))),
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Isolation::ReadCommitted => "read-committed",
+            Isolation::RepeatableRead => "repeatable-read",
+            Isolation::Serializable => "serializable",
+        }
+    }
+    pub fn level(&self) -> IsolationLevel {
+        match self {
+            Isolation::ReadCommitted => IsolationLevel::ReadCommitted,
+            Isolation::RepeatableRead => IsolationLevel::RepeatableRead,
+            Isolation::Serializable => IsolationLevel::Serializable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_param_type() {
+        for (name, param_type) in [
+            ("oid", ParamType::Oid),
+            ("INT4", ParamType::Int4),
+            ("int8", ParamType::Int8),
+            ("text", ParamType::Text),
+        ] {
+            assert_eq!(ParamType::from_string(name).unwrap(), param_type);
+            assert_eq!(param_type.column_type(), name.to_lowercase());
+        }
+        assert!(ParamType::from_string("bigint").is_err());
+    }
+
+    #[test]
+    fn test_isolation() {
+        for (name, isolation) in [
+            ("read-committed", Isolation::ReadCommitted),
+            ("REPEATABLE_READ", Isolation::RepeatableRead),
+            ("serializable", Isolation::Serializable),
+        ] {
+            assert_eq!(Isolation::from_string(name).unwrap(), isolation);
+            assert_eq!(isolation.as_str(), name.to_lowercase().replace('_', "-"));
+        }
+        assert!(Isolation::from_string("read-uncommitted").is_err());
+    }
 }
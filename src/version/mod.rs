@@ -0,0 +1,24 @@
+/*
+Version describes the build (see build.rs), so that a bug report can be traced back to it.
+*/
+
+// As printed by --version
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (git ",
+    env!("PGTPS_GIT_SHA"),
+    ", built ",
+    env!("PGTPS_BUILD_DATE"),
+    ")"
+);
+
+// One line with the build and the versions of the postgres and openssl libraries it uses
+pub fn banner() -> String {
+    format!(
+        "{} {}, postgres {}, {}",
+        env!("CARGO_PKG_NAME"),
+        VERSION,
+        env!("PGTPS_POSTGRES_VERSION"),
+        openssl::version::version()
+    )
+}